@@ -0,0 +1,228 @@
+use axum::http::StatusCode;
+use bson::{doc, oid::ObjectId};
+use futures_util::{TryStreamExt, future::BoxFuture};
+use mongodb::{Collection, Database};
+
+// Crates
+use crate::{
+    commands::{CommandContext, CommandOutcome, CommandResult, SlashCommand, caller_name},
+    controller::{
+        member_controller::add_member,
+        moderation_controller,
+        room_settings_controller::{RoomSettings, apply_settings},
+    },
+    events::{self, RoomEvent},
+    models::{
+        moderation_model::RestrictionKind,
+        room_model::{Room, RoomRole},
+        user_model::User,
+    },
+    policy::{self, Permission},
+};
+
+pub struct Me;
+pub struct Topic;
+pub struct Invite;
+pub struct Kick;
+pub struct Shrug;
+
+impl SlashCommand for Me {
+    fn name(&self) -> &'static str {
+        "me"
+    }
+
+    fn run<'a>(&'a self, ctx: CommandContext<'a>) -> BoxFuture<'a, CommandResult> {
+        Box::pin(async move {
+            if ctx.args.is_empty() {
+                return Err((StatusCode::BAD_REQUEST, "Usage: /me <action>".to_string()));
+            }
+
+            let name = caller_name(&ctx).await?;
+
+            Ok(CommandOutcome::Post(format!("* {} {}", name, ctx.args)))
+        })
+    }
+}
+
+impl SlashCommand for Shrug {
+    fn name(&self) -> &'static str {
+        "shrug"
+    }
+
+    fn run<'a>(&'a self, ctx: CommandContext<'a>) -> BoxFuture<'a, CommandResult> {
+        Box::pin(async move {
+            let shrug = r"¯\_(ツ)_/¯";
+            if ctx.args.is_empty() {
+                Ok(CommandOutcome::Post(shrug.to_string()))
+            } else {
+                Ok(CommandOutcome::Post(format!("{} {}", ctx.args, shrug)))
+            }
+        })
+    }
+}
+
+impl SlashCommand for Topic {
+    fn name(&self) -> &'static str {
+        "topic"
+    }
+
+    fn run<'a>(&'a self, ctx: CommandContext<'a>) -> BoxFuture<'a, CommandResult> {
+        Box::pin(async move {
+            let room = room_for(&ctx, Permission::EditSettings).await?;
+
+            let settings = RoomSettings {
+                topic: Some(ctx.args.to_string()),
+                ..Default::default()
+            };
+            apply_settings(ctx.db, room, ctx.caller, settings).await?;
+
+            if ctx.args.is_empty() {
+                Ok(CommandOutcome::Post("cleared the room topic".to_string()))
+            } else {
+                Ok(CommandOutcome::Post(format!("set the topic to: {}", ctx.args)))
+            }
+        })
+    }
+}
+
+impl SlashCommand for Invite {
+    fn name(&self) -> &'static str {
+        "invite"
+    }
+
+    fn run<'a>(&'a self, ctx: CommandContext<'a>) -> BoxFuture<'a, CommandResult> {
+        Box::pin(async move {
+            let room = room_for(&ctx, Permission::Invite).await?;
+            let user = find_mentioned_user(ctx.db, ctx.args, "/invite @user").await?;
+
+            let already_in_room = || {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("{} is already in the room", user.name),
+                )
+            };
+
+            if policy::is_member(ctx.db, room.id, user.id).await? {
+                return Err(already_in_room());
+            }
+
+            policy::check_not_restricted(ctx.db, room.id, user.id, RestrictionKind::Ban)
+                .await
+                .map_err(|_| {
+                    (
+                        StatusCode::FORBIDDEN,
+                        format!("{} is banned from the room", user.name),
+                    )
+                })?;
+
+            policy::check_in_workspace(ctx.db, room, user.id)
+                .await
+                .map_err(|_| {
+                    (
+                        StatusCode::FORBIDDEN,
+                        format!("{} is not part of the room's workspace", user.name),
+                    )
+                })?;
+
+            if !add_member(ctx.db, room.id, user.id, RoomRole::Member, false).await? {
+                return Err(already_in_room());
+            }
+
+            events::emit(
+                ctx.db,
+                room.id,
+                RoomEvent::MemberJoined {
+                    user_id: user.id,
+                    actor_id: ctx.caller,
+                },
+            );
+
+            Ok(CommandOutcome::Reply(format!("{} was added to the room", user.name)))
+        })
+    }
+}
+
+impl SlashCommand for Kick {
+    fn name(&self) -> &'static str {
+        "kick"
+    }
+
+    fn run<'a>(&'a self, ctx: CommandContext<'a>) -> BoxFuture<'a, CommandResult> {
+        Box::pin(async move {
+            let room = room_for(&ctx, Permission::Kick).await?;
+            let user = find_mentioned_user(ctx.db, ctx.args, "/kick @user").await?;
+
+            if !policy::is_member(ctx.db, room.id, user.id).await? {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("{} is not in the room", user.name),
+                ));
+            }
+
+            policy::check_outranks(ctx.db, room, ctx.caller, user.id, Permission::Kick).await?;
+            moderation_controller::kick(ctx.db, room, user.id, ctx.caller, None).await?;
+
+            Ok(CommandOutcome::Reply(format!("{} was removed from the room", user.name)))
+        })
+    }
+}
+
+// Room management commands only work inside a room and for the roles allowed to do it
+async fn room_for<'a>(
+    ctx: &CommandContext<'a>,
+    permission: Permission,
+) -> Result<&'a Room, (StatusCode, String)> {
+    let room = ctx.room.ok_or((
+        StatusCode::BAD_REQUEST,
+        "This command only works in a room".to_string(),
+    ))?;
+
+    policy::check(ctx.db, room, ctx.caller, permission).await?;
+
+    Ok(room)
+}
+
+// Resolves "@name" to a single user, a user id is accepted as well
+async fn find_mentioned_user(
+    db: &Database,
+    args: &str,
+    usage: &str,
+) -> Result<User, (StatusCode, String)> {
+    let user_collection: Collection<User> = db.collection("user");
+
+    let mention = args.trim().trim_start_matches('@');
+    if mention.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, format!("Usage: {usage}")));
+    }
+
+    let filter = match ObjectId::parse_str(mention) {
+        Ok(user_id) => doc! {"_id": user_id},
+        Err(_) => doc! {"name": mention},
+    };
+
+    let mut users: Vec<User> = user_collection
+        .find(filter)
+        .limit(2)
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    match users.len() {
+        0 => Err((StatusCode::NOT_FOUND, format!("No user called {mention}"))),
+        1 => Ok(users.remove(0)),
+        _ => Err((
+            StatusCode::BAD_REQUEST,
+            format!("More than one user is called {mention}, use their id instead"),
+        )),
+    }
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
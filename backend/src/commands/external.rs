@@ -0,0 +1,131 @@
+use axum::http::StatusCode;
+use bson::doc;
+use chrono::Utc;
+use mongodb::Collection;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+
+// Crates
+use crate::{
+    commands::{CommandContext, CommandOutcome, CommandResult, caller_name},
+    models::{command_model::ExternalCommand, message_model::BotSender, room_model::Room},
+    utils::http::{HTTP_CLIENT, check_public_url, sign},
+};
+
+// The caller is waiting on the send request, so handlers must answer quickly
+const HANDLER_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_RESPONSE_LEN: usize = 4000;
+
+#[derive(Deserialize)]
+struct HandlerResponse {
+    #[serde(default)]
+    text: String,
+    // "in_channel" posts to the room, anything else only answers the caller
+    #[serde(default)]
+    response_type: String,
+}
+
+// Looks up a command registered on the room, None when there is no such command
+pub async fn find(
+    ctx: &CommandContext<'_>,
+    room: &Room,
+    name: &str,
+) -> Result<Option<ExternalCommand>, (StatusCode, String)> {
+    let collection: Collection<ExternalCommand> = ctx.db.collection("external_command");
+
+    collection
+        .find_one(doc! {"room_id": room.id, "name": name.to_lowercase()})
+        .await
+        .map_err(internal_error)
+}
+
+// Sends the signed invocation to the handler and turns its answer into an outcome
+pub async fn run(ctx: CommandContext<'_>, room: &Room, command: ExternalCommand) -> CommandResult {
+    let payload = json!({
+        "command": format!("/{}", command.name),
+        "args": ctx.args,
+        "user_id": ctx.caller.to_hex(),
+        "user_name": caller_name(&ctx).await?,
+        "room_id": room.id.to_hex(),
+        "room_name": room.name,
+        "client_nonce": ctx.nonce,
+    })
+    .to_string();
+
+    // Handlers registered before urls were checked, or whose host moved since, fail here
+    check_public_url(&command.url).await.map_err(|e| {
+        println!("The handler of /{} was not called: {e}", command.name);
+        handler_error(&command)
+    })?;
+
+    let timestamp = Utc::now().timestamp().to_string();
+    let mut request = HTTP_CLIENT
+        .post(&command.url)
+        .timeout(HANDLER_TIMEOUT)
+        .header("Content-Type", "application/json")
+        .header("X-RustChat-Command", &command.name)
+        .header("X-RustChat-Timestamp", &timestamp)
+        .header(
+            "X-RustChat-Signature",
+            format!("sha256={}", sign(&command.secret, &timestamp, &payload)),
+        );
+    // Lets the handler drop an invocation it has already seen
+    if let Some(nonce) = ctx.nonce {
+        request = request.header("Idempotency-Key", nonce);
+    }
+    let response = request
+        .body(payload)
+        .send()
+        .await
+        .map_err(|e| {
+            println!("The handler of /{} failed: {e}", command.name);
+            handler_error(&command)
+        })?;
+
+    if !response.status().is_success() {
+        println!(
+            "The handler of /{} answered {}",
+            command.name,
+            response.status()
+        );
+        return Err(handler_error(&command));
+    }
+
+    let answer: HandlerResponse = response.json().await.map_err(|e| {
+        println!("The handler of /{} sent an invalid body: {e}", command.name);
+        handler_error(&command)
+    })?;
+
+    let text: String = answer.text.trim().chars().take(MAX_RESPONSE_LEN).collect();
+    if text.is_empty() {
+        return Ok(CommandOutcome::Reply(format!("/{} ran", command.name)));
+    }
+
+    if answer.response_type == "in_channel" {
+        Ok(CommandOutcome::PostAs {
+            bot: BotSender {
+                bot_id: command.id,
+                name: format!("/{}", command.name),
+            },
+            content: text,
+        })
+    } else {
+        Ok(CommandOutcome::Reply(text))
+    }
+}
+
+fn handler_error(command: &ExternalCommand) -> (StatusCode, String) {
+    (
+        StatusCode::BAD_GATEWAY,
+        format!("/{} did not respond, try again later", command.name),
+    )
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
@@ -0,0 +1,136 @@
+use axum::http::StatusCode;
+use bson::{doc, oid::ObjectId};
+use futures_util::future::BoxFuture;
+use mongodb::{Collection, Database};
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock},
+};
+
+// Crates
+use crate::models::{bot_model::Bot, message_model::BotSender, room_model::Room, user_model::User};
+
+pub mod builtin;
+pub mod external;
+
+pub type CommandResult = Result<CommandOutcome, (StatusCode, String)>;
+
+// Everything a command needs to know about the message that invoked it
+pub struct CommandContext<'a> {
+    pub db: &'a Arc<Database>,
+    pub caller: ObjectId,
+    // None when the command was sent in a DM
+    pub room: Option<&'a Room>,
+    pub args: &'a str,
+    // The client nonce of the send, at most one run per nonce reaches a command
+    pub nonce: Option<&'a str>,
+}
+
+pub enum CommandOutcome {
+    // Insert a message with this content into the conversation
+    Post(String),
+    // Answer only the caller, nothing is stored
+    Reply(String),
+    // Insert a message shown as sent by an integration instead of the caller
+    PostAs { bot: BotSender, content: String },
+}
+
+pub trait SlashCommand: Send + Sync {
+    // The name typed after the slash, in lowercase
+    fn name(&self) -> &'static str;
+
+    fn run<'a>(&'a self, ctx: CommandContext<'a>) -> BoxFuture<'a, CommandResult>;
+}
+
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: HashMap<&'static str, Box<dyn SlashCommand>>,
+}
+
+impl CommandRegistry {
+    pub fn register(&mut self, command: impl SlashCommand + 'static) -> &mut Self {
+        self.commands.insert(command.name(), Box::new(command));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn SlashCommand> {
+        self.commands
+            .get(name.to_lowercase().as_str())
+            .map(|command| command.as_ref())
+    }
+
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        registry
+            .register(builtin::Me)
+            .register(builtin::Topic)
+            .register(builtin::Invite)
+            .register(builtin::Kick)
+            .register(builtin::Shrug);
+        registry
+    }
+}
+
+// Add new commands by registering them here
+static REGISTRY: LazyLock<CommandRegistry> = LazyLock::new(CommandRegistry::with_builtins);
+
+pub fn registry() -> &'static CommandRegistry {
+    &REGISTRY
+}
+
+// Runs the builtin with this name, or else the command registered on the room.
+// None means nothing answers to the name and the message is sent as plain text
+pub async fn dispatch(name: &str, ctx: CommandContext<'_>) -> Option<CommandResult> {
+    if let Some(command) = registry().get(name) {
+        return Some(command.run(ctx).await);
+    }
+
+    let room = ctx.room?;
+    match external::find(&ctx, room, name).await {
+        Ok(Some(command)) => Some(external::run(ctx, room, command).await),
+        Ok(None) => None,
+        Err(e) => Some(Err(e)),
+    }
+}
+
+// Splits "/name some args" into ("name", "some args")
+pub fn parse_command(content: &str) -> Option<(&str, &str)> {
+    let rest = content.strip_prefix('/')?;
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((name, args.trim()))
+}
+
+// Bots can invoke commands too, so fall back to the bot collection
+pub async fn caller_name(ctx: &CommandContext<'_>) -> Result<String, (StatusCode, String)> {
+    let user_collection: Collection<User> = ctx.db.collection("user");
+    let bot_collection: Collection<Bot> = ctx.db.collection("bot");
+
+    if let Some(user) = user_collection
+        .find_one(doc! {"_id": ctx.caller})
+        .await
+        .map_err(internal_error)?
+    {
+        return Ok(user.name);
+    }
+
+    let bot = bot_collection
+        .find_one(doc! {"_id": ctx.caller})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    Ok(bot.name)
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
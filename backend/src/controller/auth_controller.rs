@@ -17,12 +17,6 @@ pub struct RegisterRequest {
     pub password: String,
 }
 
-#[derive(Serialize)]
-pub struct AuthResponse {
-    pub msg: String,
-    pub user_id: ObjectId,
-}
-
 #[derive(Debug, Deserialize, Serialize)]
 pub struct JWTClaims {
     user_id: ObjectId,
@@ -60,7 +54,7 @@ pub async fn register(
 
     match collection.find_one(filter).await {
         Ok(Some(_)) => {
-            return Err((StatusCode::BAD_REQUEST, "Email already exists".to_string()));
+            Err((StatusCode::BAD_REQUEST, "Email already exists".to_string()))
         }
         Ok(None) => {
             let hashed = hash(&payload.password, DEFAULT_COST)
@@ -68,10 +62,14 @@ pub async fn register(
 
             let user_id = ObjectId::new();
             let user = User {
-                id: user_id.clone(),
+                id: user_id,
                 name: payload.name,
                 email: payload.email,
                 password: hashed,
+                avatar: None,
+                last_seen_at: None,
+                is_admin: false,
+                deleting_at: None,
             };
 
             collection.insert_one(&user).await
@@ -83,7 +81,7 @@ pub async fn register(
             let exp = now + chrono::Duration::hours(TOKEN_EXPIRY);
 
             let claims = JWTClaims {
-                user_id: user_id.clone(),
+                user_id,
                 exp: exp.timestamp() as usize,
                 iat: now.timestamp() as usize,
             };
@@ -95,14 +93,14 @@ pub async fn register(
                 &EncodingKey::from_secret(secret.as_ref()),
             ).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to encode token".to_string()))?;
 
-            return Ok(Json(LoginResponse {
+            Ok(Json(LoginResponse {
                 msg: "User registered & logged in successfully".to_string(),
                 id: user_id,
                 token,
-            }));
+            }))
         }
         Err(_) => {
-            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to find user".to_string()));
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to find user".to_string()))
         }
     }
 }
@@ -120,7 +118,8 @@ pub async fn login(
         ));
     }
 
-    let filter = doc! { "email": payload.email };
+    // Accounts that are being deleted can't log in again
+    let filter = doc! { "email": payload.email, "deleting_at": null };
 
     match collection.find_one(filter).await {
         Ok(Some(user)) => match verify(&payload.password, &user.password) {
@@ -132,7 +131,7 @@ pub async fn login(
                 let exp = now + chrono::Duration::hours(TOKEN_EXPIRY);
 
                 let claims = JWTClaims {
-                    user_id: user_id.clone(),
+                    user_id,
                     exp: exp.timestamp() as usize,
                     iat: now.timestamp() as usize,
                 };
@@ -144,41 +143,41 @@ pub async fn login(
                     &EncodingKey::from_secret(secret.as_ref()),
                 ) {
                     Ok(token) => {
-                        return Ok(Json(LoginResponse {
+                        Ok(Json(LoginResponse {
                             msg: "User created Successfully".to_string(),
                             id: user_id,
-                            token: token,
-                        }));
+                            token,
+                        }))
                     }
                     Err(e) => {
                         print!("some error occured: {}", e);
-                        return Err((
+                        Err((
                             StatusCode::INTERNAL_SERVER_ERROR,
                             "Internal Server Error".to_string(),
-                        ));
+                        ))
                     }
                 }
             }
             Ok(false) => {
-                return Err((StatusCode::UNAUTHORIZED, "Invalid password".to_string()));
+                Err((StatusCode::UNAUTHORIZED, "Invalid password".to_string()))
             }
             Err(e) => {
                 println!("Error in finding the email: {}", e);
-                return Err((
+                Err((
                     StatusCode::INTERNAL_SERVER_ERROR,
                     "Error in verifying the password".to_string(),
-                ));
+                ))
             }
         },
         Ok(None) => {
-            return Err((StatusCode::NOT_FOUND, "Email not Found".to_string()));
+            Err((StatusCode::NOT_FOUND, "Email not Found".to_string()))
         }
         Err(e) => {
             println!("Database error while finding user: {}", e);
-            return Err((
+            Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal Server Error".to_string(),
-            ));
+            ))
         }
     }
 }
@@ -0,0 +1,388 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use bson::{doc, oid::ObjectId};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    controller::member_controller::remove_member,
+    events::{self, RoomEvent},
+    middleware::auth_middleware::Claims,
+    models::{
+        bot_model::{BOT_SCOPES, BOT_TOKEN_PREFIX, Bot, BotToken},
+        membership_model::Membership,
+        room_model::Room,
+    },
+    policy::{self, Permission},
+    utils::token::{generate_token, hash_token},
+};
+
+// DTOs
+#[derive(Deserialize)]
+pub struct BotRequest {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct BotTokenRequest {
+    scopes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct BotResponse {
+    msg: String,
+    bot_id: ObjectId,
+}
+
+#[derive(Serialize)]
+pub struct BotTokenResponse {
+    msg: String,
+    token_id: ObjectId,
+    // Only returned once, the server keeps a hash
+    token: String,
+    scopes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Bots {
+    id: ObjectId,
+    name: String,
+    created_at: DateTime,
+    is_bot: bool,
+    tokens: Vec<BotTokens>,
+}
+
+#[derive(Serialize)]
+pub struct BotTokens {
+    id: ObjectId,
+    scopes: Vec<String>,
+    created_at: DateTime,
+    revoked: bool,
+}
+
+pub async fn create_bot(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Json(payload): Json<BotRequest>,
+) -> Result<Json<BotResponse>, (StatusCode, String)> {
+    let collection: Collection<Bot> = db.collection("bot");
+
+    let name = payload.name.trim().to_string();
+    if name.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Name is required".to_string()));
+    }
+
+    let bot = Bot {
+        id: ObjectId::new(),
+        name,
+        owner: claims.user_id,
+        created_at: DateTime::now(),
+    };
+
+    match collection.insert_one(&bot).await {
+        Ok(_) => {
+            Ok(Json(BotResponse {
+                msg: format!("The bot {} was created", bot.name),
+                bot_id: bot.id,
+            }))
+        }
+        Err(e) => {
+            println!("Error in creating the bot: {e}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ))
+        }
+    }
+}
+
+pub async fn get_my_bots(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+) -> Result<Json<Vec<Bots>>, (StatusCode, String)> {
+    let collection: Collection<Bot> = db.collection("bot");
+    let token_collection: Collection<BotToken> = db.collection("bot_token");
+
+    let bots: Vec<Bot> = collection
+        .find(doc! {"owner": claims.user_id})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    let mut results = Vec::new();
+    for bot in bots {
+        let tokens: Vec<BotToken> = token_collection
+            .find(doc! {"bot_id": bot.id})
+            .await
+            .map_err(internal_error)?
+            .try_collect()
+            .await
+            .map_err(internal_error)?;
+
+        results.push(Bots {
+            id: bot.id,
+            name: bot.name,
+            created_at: bot.created_at,
+            is_bot: true,
+            tokens: tokens
+                .into_iter()
+                .map(|token| BotTokens {
+                    id: token.id,
+                    scopes: token.scopes,
+                    created_at: token.created_at,
+                    revoked: token.revoked,
+                })
+                .collect(),
+        });
+    }
+
+    Ok(Json(results))
+}
+
+pub async fn create_bot_token(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+    Json(payload): Json<BotTokenRequest>,
+) -> Result<Json<BotTokenResponse>, (StatusCode, String)> {
+    let token_collection: Collection<BotToken> = db.collection("bot_token");
+
+    let bot = find_owned_bot(&db, &id, claims.user_id).await?;
+
+    if payload.scopes.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The token needs at least one scope".to_string(),
+        ));
+    }
+
+    if let Some(unknown) = payload
+        .scopes
+        .iter()
+        .find(|scope| !BOT_SCOPES.contains(&scope.as_str()))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Unknown scope {unknown}, expected one of {}",
+                BOT_SCOPES.join(", ")
+            ),
+        ));
+    }
+
+    let token = format!("{BOT_TOKEN_PREFIX}{}", generate_token());
+    let bot_token = BotToken {
+        id: ObjectId::new(),
+        bot_id: bot.id,
+        token_hash: hash_token(&token),
+        scopes: payload.scopes,
+        created_at: DateTime::now(),
+        revoked: false,
+    };
+
+    match token_collection.insert_one(&bot_token).await {
+        Ok(_) => {
+            Ok(Json(BotTokenResponse {
+                msg: format!("A new token was created for {}", bot.name),
+                token_id: bot_token.id,
+                token,
+                scopes: bot_token.scopes,
+            }))
+        }
+        Err(e) => {
+            println!("Error in creating the bot token: {e}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ))
+        }
+    }
+}
+
+pub async fn revoke_bot_token(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let token_collection: Collection<BotToken> = db.collection("bot_token");
+
+    let token_obj_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Token Id".to_string()))?;
+
+    let token = token_collection
+        .find_one(doc! {"_id": token_obj_id})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Token Not Found".to_string()))?;
+
+    find_owned_bot(&db, &token.bot_id.to_hex(), claims.user_id).await?;
+
+    token_collection
+        .update_one(
+            doc! {"_id": token.id},
+            doc! { "$set": { "revoked": true } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    Ok("The token is revoked".to_string())
+}
+
+pub async fn delete_bot(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let bot = find_owned_bot(&db, &id, claims.user_id).await?;
+
+    remove_bot(&db, &bot).await?;
+
+    Ok(format!("The bot {} is deleted", bot.name))
+}
+
+// Revokes the bot's tokens and takes it out of every room, then deletes it
+pub async fn remove_bot(db: &Arc<Database>, bot: &Bot) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<Bot> = db.collection("bot");
+    let token_collection: Collection<BotToken> = db.collection("bot_token");
+    let room_collection: Collection<Room> = db.collection("room");
+    let membership_collection: Collection<Membership> = db.collection("membership");
+
+    token_collection
+        .delete_many(doc! {"bot_id": bot.id})
+        .await
+        .map_err(internal_error)?;
+
+    // Every room, archived ones too, so unarchiving doesn't bring the bot back
+    let joined = membership_collection
+        .distinct("room_id", doc! {"user_id": bot.id})
+        .await
+        .map_err(internal_error)?;
+
+    // Each membership goes with its count, so a retry after a failure doesn't count twice
+    for room_id in joined.iter().filter_map(|id| id.as_object_id()) {
+        if remove_member(db, room_id, bot.id).await? {
+            events::emit(
+                db,
+                room_id,
+                RoomEvent::MemberLeft {
+                    user_id: bot.id,
+                    actor_id: bot.owner,
+                },
+            );
+        }
+    }
+
+    room_collection
+        .update_many(
+            doc! {"invited_bots": bot.id},
+            doc! { "$pull": { "invited_bots": bot.id } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    collection
+        .delete_one(doc! {"_id": bot.id})
+        .await
+        .map_err(internal_error)?;
+
+    Ok(())
+}
+
+// A member allowed to invite adds a bot, the bot then joins through join_room
+pub async fn invite_bot(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, bot_id)): Path<(String, String)>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<Bot> = db.collection("bot");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let room_obj_id = ObjectId::parse_str(&room_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
+    let bot_obj_id = ObjectId::parse_str(&bot_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Bot Id".to_string()))?;
+
+    let room = room_collection
+        .find_one(doc! {"_id": room_obj_id})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Room not found".to_string()))?;
+
+    policy::check(&db, &room, claims.user_id, Permission::Invite).await?;
+    policy::check_not_archived(&room)?;
+
+    let bot = collection
+        .find_one(doc! {"_id": bot_obj_id})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Bot Not Found".to_string()))?;
+
+    if policy::is_member(&db, room.id, bot.id).await? {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The bot is already in the room".to_string(),
+        ));
+    }
+
+    // A bot acts for its owner, so it only gets into rooms its owner could join
+    policy::check_in_workspace(&db, &room, bot.owner)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::FORBIDDEN,
+                "The owner of the bot is not part of the room's workspace".to_string(),
+            )
+        })?;
+
+    room_collection
+        .update_one(
+            doc! {"_id": room.id},
+            doc! { "$addToSet": { "invited_bots": bot.id } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    Ok(format!("{} was invited to {}", bot.name, room.name))
+}
+
+async fn find_owned_bot(
+    db: &Database,
+    id: &str,
+    user_id: ObjectId,
+) -> Result<Bot, (StatusCode, String)> {
+    let collection: Collection<Bot> = db.collection("bot");
+
+    let bot_obj_id = ObjectId::parse_str(id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Bot Id".to_string()))?;
+
+    let bot = collection
+        .find_one(doc! {"_id": bot_obj_id})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Bot Not Found".to_string()))?;
+
+    if bot.owner != user_id {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You don't manage this bot".to_string(),
+        ));
+    }
+
+    Ok(bot)
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
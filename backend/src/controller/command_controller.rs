@@ -0,0 +1,182 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use bson::{doc, oid::ObjectId};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    commands::registry,
+    middleware::auth_middleware::Claims,
+    models::command_model::ExternalCommand,
+    policy::{Permission, find_room},
+    utils::{db::is_duplicate_key_error, http::check_public_url, token::generate_token},
+};
+
+const MAX_COMMAND_NAME_LEN: usize = 32;
+
+// DTOs
+#[derive(Deserialize)]
+pub struct CommandRequest {
+    name: String,
+    url: String,
+}
+
+#[derive(Serialize)]
+pub struct CommandCreatedResponse {
+    msg: String,
+    id: ObjectId,
+    // Only returned once, used to verify the X-RustChat-Signature header
+    secret: String,
+}
+
+#[derive(Serialize)]
+pub struct Commands {
+    id: ObjectId,
+    room_id: ObjectId,
+    name: String,
+    url: String,
+    created_by: ObjectId,
+    created_at: DateTime,
+}
+
+pub async fn create_command(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+    Json(payload): Json<CommandRequest>,
+) -> Result<Json<CommandCreatedResponse>, (StatusCode, String)> {
+    let collection: Collection<ExternalCommand> = db.collection("external_command");
+
+    let name = payload.name.trim().trim_start_matches('/').to_lowercase();
+
+    if name.is_empty()
+        || name.len() > MAX_COMMAND_NAME_LEN
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The name must be 1 to {MAX_COMMAND_NAME_LEN} letters, digits, - or _"),
+        ));
+    }
+
+    if registry().get(&name).is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("/{name} is a built in command"),
+        ));
+    }
+
+    check_public_url(&payload.url)
+        .await
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg))?;
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::EditSettings).await?;
+
+    let command = ExternalCommand {
+        id: ObjectId::new(),
+        room_id: room.id,
+        name,
+        url: payload.url,
+        secret: generate_token(),
+        created_by: claims.user_id,
+        created_at: DateTime::now(),
+    };
+
+    match collection.insert_one(&command).await {
+        Ok(_) => {
+            Ok(Json(CommandCreatedResponse {
+                msg: format!("/{} will be sent to {}", command.name, command.url),
+                id: command.id,
+                secret: command.secret,
+            }))
+        }
+        Err(e) if is_duplicate_key_error(&e) => {
+            Err((
+                StatusCode::CONFLICT,
+                format!("/{} is already registered in this room", command.name),
+            ))
+        }
+        Err(e) => {
+            println!("Error in creating the command: {e}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ))
+        }
+    }
+}
+
+pub async fn get_room_commands(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<Commands>>, (StatusCode, String)> {
+    let collection: Collection<ExternalCommand> = db.collection("external_command");
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::EditSettings).await?;
+
+    let commands: Vec<ExternalCommand> = collection
+        .find(doc! {"room_id": room.id})
+        .sort(doc! {"name": 1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(
+        commands
+            .into_iter()
+            .map(|command| Commands {
+                id: command.id,
+                room_id: command.room_id,
+                name: command.name,
+                url: command.url,
+                created_by: command.created_by,
+                created_at: command.created_at,
+            })
+            .collect(),
+    ))
+}
+
+pub async fn delete_command(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<ExternalCommand> = db.collection("external_command");
+
+    let command_obj_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Command Id".to_string()))?;
+
+    let command = collection
+        .find_one(doc! {"_id": command_obj_id})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Command Not Found".to_string()))?;
+
+    find_room(&db, &command.room_id.to_hex(), claims.user_id, Permission::EditSettings).await?;
+
+    collection
+        .delete_one(doc! {"_id": command.id})
+        .await
+        .map_err(internal_error)?;
+
+    Ok(format!("/{} is deleted", command.name))
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
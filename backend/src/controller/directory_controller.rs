@@ -0,0 +1,293 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use bson::{Bson, Document, doc, oid::ObjectId};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    controller::workspace_controller::workspaces_of,
+    middleware::auth_middleware::Claims,
+    models::{
+        membership_model::Membership,
+        room_model::{Room, RoomVisibility},
+    },
+    utils::query::{SortValue, after_cursor, encode_cursor, escape_regex, fuzzy_regex},
+};
+
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 50;
+const MAX_SEARCH_LEN: usize = 100;
+
+// DTOs
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectorySort {
+    #[default]
+    Name,
+    Members,
+    Activity,
+}
+
+#[derive(Deserialize)]
+pub struct DirectoryQuery {
+    // Rooms whose name starts with this
+    prefix: Option<String>,
+    // Fuzzy match on the name, or a plain match on the description
+    q: Option<String>,
+    // Comma separated, a room needs all of them
+    tags: Option<String>,
+    // Only the rooms of this workspace
+    workspace: Option<String>,
+    #[serde(default)]
+    sort: DirectorySort,
+    cursor: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DirectoryRoom {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    avatar: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    visibility: RoomVisibility,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    workspace_id: Option<ObjectId>,
+    member_count: u32,
+    last_activity_at: DateTime,
+    joined: bool,
+    archived: bool,
+    // Value of the sorted field, only used to build the next cursor
+    #[serde(skip_serializing)]
+    sort_key: Bson,
+}
+
+#[derive(Serialize)]
+pub struct DirectoryPage {
+    rooms: Vec<DirectoryRoom>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+impl DirectorySort {
+    fn as_str(self) -> &'static str {
+        match self {
+            DirectorySort::Name => "name",
+            DirectorySort::Members => "members",
+            DirectorySort::Activity => "activity",
+        }
+    }
+
+    // Cursors from the archived part of the list can't be used for the rest of it
+    fn listing(self, archived: bool) -> String {
+        if archived {
+            format!("{}.archived", self.as_str())
+        } else {
+            self.as_str().to_string()
+        }
+    }
+
+    // The type of the sort key, a cursor holding anything else is rejected
+    fn value(self) -> SortValue {
+        match self {
+            DirectorySort::Name => SortValue::Text,
+            DirectorySort::Members => SortValue::Number,
+            DirectorySort::Activity => SortValue::Date,
+        }
+    }
+
+    // The computed field the pipeline sorts on, and whether it goes up
+    fn key(self) -> (&'static str, bool) {
+        match self {
+            DirectorySort::Name => ("sort_name", true),
+            DirectorySort::Members => ("member_count", false),
+            DirectorySort::Activity => ("last_activity_at", false),
+        }
+    }
+}
+
+pub async fn get_room_directory(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Query(query): Query<DirectoryQuery>,
+) -> Result<Json<DirectoryPage>, (StatusCode, String)> {
+    let collection: Collection<Room> = db.collection("room");
+    let membership_collection: Collection<Membership> = db.collection("membership");
+
+    let my_rooms = membership_collection
+        .distinct("room_id", doc! {"user_id": claims.user_id})
+        .await
+        .map_err(internal_error)?;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let (key, ascending) = query.sort.key();
+    let order = if ascending { 1 } else { -1 };
+
+    let my_workspaces = workspaces_of(&db, claims.user_id).await?;
+
+    let mut filters = vec![visible_rooms(&my_rooms, &my_workspaces)];
+
+    if let Some(workspace) = query.workspace {
+        let workspace_obj_id = ObjectId::parse_str(&workspace)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Workspace Id".to_string()))?;
+        filters.push(doc! { "workspace_id": workspace_obj_id });
+    }
+
+    if let Some(prefix) = non_empty(query.prefix)? {
+        filters.push(doc! {
+            "name": { "$regex": format!("^{}", escape_regex(&prefix)), "$options": "i" }
+        });
+    }
+
+    if let Some(search) = non_empty(query.q)? {
+        filters.push(doc! {
+            "$or": [
+                { "name": { "$regex": fuzzy_regex(&search), "$options": "i" } },
+                { "description": { "$regex": escape_regex(&search), "$options": "i" } }
+            ]
+        });
+    }
+
+    if let Some(tags) = query.tags {
+        // Tags are stored in lowercase without the leading '#'
+        let tags: Vec<String> = tags
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches('#').to_lowercase())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        if !tags.is_empty() {
+            filters.push(doc! { "tags": { "$all": tags } });
+        }
+    }
+
+    let mut pipeline = vec![
+        doc! { "$match": { "$and": filters } },
+        doc! { "$addFields": {
+            "sort_name": { "$toLower": "$name" },
+            "member_count": { "$ifNull": ["$member_count", 0] },
+            // Rooms without messages fall back to their creation time
+            "last_activity_at": {
+                "$ifNull": ["$last_activity_at", "$created_at", { "$toDate": "$_id" }]
+            },
+            "archived": { "$gt": ["$archived_at", null] },
+        } },
+    ];
+
+    // Archived rooms come after the others, the listing of the cursor tells in which part
+    // the last page ended
+    if let Some(cursor) = &query.cursor {
+        let after = |archived| {
+            after_cursor(
+                cursor,
+                &query.sort.listing(archived),
+                key,
+                query.sort.value(),
+                ascending,
+            )
+        };
+        let filter = match after(false) {
+            Ok(after) => doc! {
+                "$or": [ { "archived": false, "$and": [after] }, { "archived": true } ]
+            },
+            Err(_) => doc! { "archived": true, "$and": [after(true)?] },
+        };
+        pipeline.push(doc! { "$match": filter });
+    }
+
+    pipeline.extend([
+        doc! { "$sort": { "archived": 1, key: order, "_id": order } },
+        // One extra to know if there is a next page
+        doc! { "$limit": i64::from(limit) + 1 },
+        doc! { "$project": {
+            "name": 1,
+            "topic": 1,
+            "description": 1,
+            "avatar": 1,
+            "tags": 1,
+            "visibility": 1,
+            "workspace_id": 1,
+            "member_count": 1,
+            "last_activity_at": 1,
+            "joined": { "$in": ["$_id", &my_rooms] },
+            "archived": 1,
+            "sort_key": format!("${key}"),
+        } },
+    ]);
+
+    let mut rooms: Vec<DirectoryRoom> = collection
+        .aggregate(pipeline)
+        .with_type::<DirectoryRoom>()
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    let next_cursor = if rooms.len() > limit as usize {
+        rooms.truncate(limit as usize);
+        rooms
+            .last()
+            .map(|room| {
+                let listing = query.sort.listing(room.archived);
+                encode_cursor(&listing, room.sort_key.clone(), room.id)
+            })
+    } else {
+        None
+    };
+
+    Ok(Json(DirectoryPage { rooms, next_cursor }))
+}
+
+// Hidden rooms are only listed to their own members, and workspace rooms to the
+// members of the workspace
+pub fn visible_rooms(my_rooms: &[Bson], my_workspaces: &[Bson]) -> Document {
+    doc! { "$and": [
+        { "$or": [ { "visibility": { "$ne": "hidden" } }, { "_id": { "$in": my_rooms } } ] },
+        {
+            "$or": [
+                { "workspace_id": { "$exists": false } },
+                { "workspace_id": { "$in": my_workspaces } }
+            ]
+        },
+    ] }
+}
+
+fn non_empty(text: Option<String>) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(text) = text.map(|text| text.trim().to_string()) else {
+        return Ok(None);
+    };
+
+    if text.chars().count() > MAX_SEARCH_LEN {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Searches can be at most {MAX_SEARCH_LEN} characters"),
+        ));
+    }
+
+    Ok((!text.is_empty()).then_some(text))
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
@@ -0,0 +1,460 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use bson::{doc, oid::ObjectId};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    middleware::auth_middleware::Claims,
+    models::{
+        group_dm_model::{GroupDm, MAX_GROUP_DM_MEMBERS, MIN_GROUP_DM_MEMBERS, member_key},
+        message_model::Message,
+        user_model::User,
+    },
+    utils::db::is_duplicate_key_error,
+};
+
+const MAX_NAME_LEN: usize = 100;
+
+// DTOs
+#[derive(Deserialize)]
+pub struct GroupDmRequest {
+    // The other people, the caller is always part of the group
+    user_ids: Vec<String>,
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct GroupDmResponse {
+    msg: String,
+    group_id: ObjectId,
+}
+
+#[derive(Serialize)]
+pub struct GroupMember {
+    id: ObjectId,
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct GroupDms {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    members: Vec<GroupMember>,
+    created_at: DateTime,
+}
+
+// Starting a group with the same people again reopens the existing one
+pub async fn open_group_dm(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Json(payload): Json<GroupDmRequest>,
+) -> Result<Json<GroupDmResponse>, (StatusCode, String)> {
+    let collection: Collection<GroupDm> = db.collection("group_dm");
+    let user_collection: Collection<User> = db.collection("user");
+
+    let members = group_members(claims.user_id, &payload.user_ids)?;
+
+    if let Some(group) = collection
+        .find_one(doc! {"member_key": member_key(&members)})
+        .await
+        .map_err(internal_error)?
+    {
+        return Ok(Json(GroupDmResponse {
+            msg: "The group DM was reopened".to_string(),
+            group_id: group.id,
+        }));
+    }
+
+    // Bots have no user record, so they can't be added
+    let found = user_collection
+        .count_documents(doc! {"_id": { "$in": &members }})
+        .await
+        .map_err(internal_error)?;
+
+    if found != members.len() as u64 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Some of the users were not found".to_string(),
+        ));
+    }
+
+    let name = payload
+        .name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    if name
+        .as_ref()
+        .is_some_and(|name| name.chars().count() > MAX_NAME_LEN)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The name can be at most {MAX_NAME_LEN} characters"),
+        ));
+    }
+
+    let group = GroupDm::new(members, name);
+
+    match collection.insert_one(&group).await {
+        Ok(_) => Ok(Json(GroupDmResponse {
+            msg: "The group DM was created".to_string(),
+            group_id: group.id,
+        })),
+        // Someone opened the same group at the same time
+        Err(e) if is_duplicate_key_error(&e) => {
+            let existing = collection
+                .find_one(doc! {"member_key": &group.member_key})
+                .await
+                .map_err(internal_error)?
+                .ok_or((
+                    StatusCode::CONFLICT,
+                    "The group DM is still being created, try again".to_string(),
+                ))?;
+            Ok(Json(GroupDmResponse {
+                msg: "The group DM was reopened".to_string(),
+                group_id: existing.id,
+            }))
+        }
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+// The caller and the people they named, sorted and without repeats, so the same people
+// always make the same group
+fn group_members(
+    caller: ObjectId,
+    user_ids: &[String],
+) -> Result<Vec<ObjectId>, (StatusCode, String)> {
+    let mut members = vec![caller];
+    for user_id in user_ids {
+        let user_obj_id = ObjectId::parse_str(user_id)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid User Id".to_string()))?;
+        members.push(user_obj_id);
+    }
+    members.sort();
+    members.dedup();
+
+    if !(MIN_GROUP_DM_MEMBERS..=MAX_GROUP_DM_MEMBERS).contains(&members.len()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "A group DM has {MIN_GROUP_DM_MEMBERS} to {MAX_GROUP_DM_MEMBERS} people, you included"
+            ),
+        ));
+    }
+
+    Ok(members)
+}
+
+pub async fn get_my_group_dms(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+) -> Result<Json<Vec<GroupDms>>, (StatusCode, String)> {
+    let collection: Collection<GroupDm> = db.collection("group_dm");
+    let user_collection: Collection<User> = db.collection("user");
+
+    let groups: Vec<GroupDm> = collection
+        .find(doc! {"members": claims.user_id})
+        .sort(doc! {"created_at": -1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    let mut user_ids: Vec<ObjectId> = groups
+        .iter()
+        .flat_map(|group| group.members.iter().copied())
+        .collect();
+    user_ids.sort();
+    user_ids.dedup();
+
+    let users: Vec<User> = user_collection
+        .find(doc! {"_id": { "$in": user_ids }})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(
+        groups
+            .into_iter()
+            .map(|group| GroupDms {
+                id: group.id,
+                name: group.name,
+                // Deleted accounts drop out of the list
+                members: group
+                    .members
+                    .iter()
+                    .filter_map(|member| {
+                        let user = users.iter().find(|user| user.id == *member)?;
+                        Some(GroupMember {
+                            id: user.id,
+                            name: user.name.clone(),
+                        })
+                    })
+                    .collect(),
+                created_at: group.created_at,
+            })
+            .collect(),
+    ))
+}
+
+pub async fn get_group_messages(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<Message>>, (StatusCode, String)> {
+    let message_collection: Collection<Message> = db.collection("message");
+
+    let group_obj_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Group Id".to_string()))?;
+
+    let group = find_group_dm(&db, group_obj_id)
+        .await?
+        .ok_or((StatusCode::NOT_FOUND, "Group DM not found".to_string()))?;
+
+    check_group_member(&group, claims.user_id)?;
+
+    let messages: Vec<Message> = message_collection
+        .find(doc! {"group_id": group.id})
+        .sort(doc! {"timestamp": 1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(messages))
+}
+
+pub async fn find_group_dm(
+    db: &Database,
+    id: ObjectId,
+) -> Result<Option<GroupDm>, (StatusCode, String)> {
+    let collection: Collection<GroupDm> = db.collection("group_dm");
+
+    collection
+        .find_one(doc! {"_id": id})
+        .await
+        .map_err(internal_error)
+}
+
+pub fn check_group_member(group: &GroupDm, user_id: ObjectId) -> Result<(), (StatusCode, String)> {
+    if !group.members.contains(&user_id) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You are not part of this group DM".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Takes a deleted account out of its group DMs. A group needs three people, so one left
+// with two is closed along with its history. When the rest already share another group,
+// the history moves there
+pub async fn remove_from_group_dms(
+    db: &Database,
+    user_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<GroupDm> = db.collection("group_dm");
+    let message_collection: Collection<Message> = db.collection("message");
+
+    let groups: Vec<GroupDm> = collection
+        .find(doc! {"members": user_id})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    for group in groups {
+        // Still sorted, the order is kept
+        let members: Vec<ObjectId> = group
+            .members
+            .iter()
+            .copied()
+            .filter(|member| *member != user_id)
+            .collect();
+
+        if members.len() >= MIN_GROUP_DM_MEMBERS {
+            let key = member_key(&members);
+            match collection
+                .update_one(
+                    doc! {"_id": group.id},
+                    doc! { "$set": { "members": members, "member_key": &key } },
+                )
+                .await
+            {
+                Ok(_) => continue,
+                Err(e) if is_duplicate_key_error(&e) => {
+                    if let Some(existing) = collection
+                        .find_one(doc! {"member_key": &key})
+                        .await
+                        .map_err(internal_error)?
+                    {
+                        message_collection
+                            .update_many(
+                                doc! {"group_id": group.id},
+                                doc! { "$set": { "group_id": existing.id } },
+                            )
+                            .await
+                            .map_err(internal_error)?;
+                    }
+                }
+                Err(e) => return Err(internal_error(e)),
+            }
+        }
+
+        // The messages go first, so a retry still finds the group
+        message_collection
+            .delete_many(doc! {"group_id": group.id})
+            .await
+            .map_err(internal_error)?;
+        collection
+            .delete_one(doc! {"_id": group.id})
+            .await
+            .map_err(internal_error)?;
+    }
+
+    Ok(())
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::Client;
+    use std::env;
+
+    fn ids(count: usize) -> Vec<ObjectId> {
+        (0..count).map(|_| ObjectId::new()).collect()
+    }
+
+    #[test]
+    fn repeated_members_count_once() {
+        let [me, a, b] = ids(3)[..] else {
+            unreachable!()
+        };
+        let named = [a, b, a, me, b].map(|id| id.to_hex());
+
+        let members = group_members(me, &named).unwrap();
+
+        let mut expected = vec![me, a, b];
+        expected.sort();
+        assert_eq!(members, expected);
+        assert_eq!(GroupDm::new(vec![b, me, a, b], None).members, expected);
+    }
+
+    #[test]
+    fn the_same_people_make_the_same_key() {
+        let [me, a, b] = ids(3)[..] else {
+            unreachable!()
+        };
+
+        let first = group_members(me, &[a.to_hex(), b.to_hex()]).unwrap();
+        let second = group_members(a, &[b.to_hex(), me.to_hex(), b.to_hex()]).unwrap();
+
+        assert_eq!(member_key(&first), member_key(&second));
+    }
+
+    #[test]
+    fn repeats_dont_make_up_the_minimum() {
+        let [me, a] = ids(2)[..] else { unreachable!() };
+
+        let err = group_members(me, &[a.to_hex(), a.to_hex(), me.to_hex()]).unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+
+        let too_many: Vec<String> = ids(MAX_GROUP_DM_MEMBERS)
+            .iter()
+            .map(|id| id.to_hex())
+            .collect();
+        assert_eq!(
+            group_members(me, &too_many).unwrap_err().0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB in TEST_DB, run with --ignored"]
+    async fn opening_the_same_group_again_reopens_it() {
+        let url = env::var("TEST_DB").expect("TEST_DB is not set");
+        let client = Client::with_uri_str(&url).await.unwrap();
+        let db = Arc::new(client.database(&format!("RustChatTest{}", ObjectId::new())));
+
+        let users = ids(3);
+        let user_collection: Collection<bson::Document> = db.collection("user");
+        for id in &users {
+            user_collection
+                .insert_one(doc! { "_id": id, "name": id.to_hex(), "email": format!("{id}@test"), "password": "" })
+                .await
+                .unwrap();
+        }
+        db.collection::<GroupDm>("group_dm")
+            .create_index(
+                mongodb::IndexModel::builder()
+                    .keys(doc! { "member_key": 1 })
+                    .options(
+                        mongodb::options::IndexOptions::builder()
+                            .unique(true)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .await
+            .unwrap();
+
+        let claims = |user_id| Claims {
+            user_id,
+            exp: 0,
+            iat: 0,
+            bot: None,
+        };
+        let open = |caller: ObjectId, others: Vec<ObjectId>| {
+            let db = db.clone();
+            async move {
+                let request = GroupDmRequest {
+                    user_ids: others.iter().map(|id| id.to_hex()).collect(),
+                    name: None,
+                };
+                open_group_dm(State(db), claims(caller), Json(request))
+                    .await
+                    .unwrap()
+                    .0
+            }
+        };
+
+        let created = open(users[0], vec![users[1], users[2]]).await;
+        let reopened = open(users[2], vec![users[1], users[0], users[1]]).await;
+
+        assert_eq!(created.msg, "The group DM was created");
+        assert_eq!(reopened.msg, "The group DM was reopened");
+        assert_eq!(reopened.group_id, created.group_id);
+        assert_eq!(
+            db.collection::<GroupDm>("group_dm")
+                .count_documents(doc! {})
+                .await
+                .unwrap(),
+            1
+        );
+
+        db.as_ref().drop().await.unwrap();
+    }
+}
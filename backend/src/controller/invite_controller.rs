@@ -0,0 +1,237 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use bson::{doc, oid::ObjectId};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    events::{self, RoomEvent},
+    middleware::auth_middleware::Claims,
+    controller::member_controller::add_member,
+    models::{
+        invite_model::RoomInvite,
+        room_model::{Room, RoomRole},
+    },
+    policy::{self, Permission, find_room},
+    utils::{db::is_duplicate_key_error, token::generate_code},
+};
+
+const INVITE_CODE_LEN: usize = 10;
+
+// DTOs
+#[derive(Deserialize)]
+pub struct InviteRequest {
+    // Seconds until the code stops working, it never expires when missing
+    expires_in: Option<u64>,
+    max_uses: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct InviteResponse {
+    msg: String,
+    id: ObjectId,
+    code: String,
+}
+
+#[derive(Serialize)]
+pub struct JoinResponse {
+    msg: String,
+    room_id: ObjectId,
+}
+
+pub async fn create_invite(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+    Json(payload): Json<InviteRequest>,
+) -> Result<Json<InviteResponse>, (StatusCode, String)> {
+    let collection: Collection<RoomInvite> = db.collection("room_invite");
+
+    if payload.expires_in == Some(0) || payload.max_uses == Some(0) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The expiry and the max uses must be above zero".to_string(),
+        ));
+    }
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::Invite).await?;
+
+    let now = DateTime::now();
+    let mut invite = RoomInvite {
+        id: ObjectId::new(),
+        room_id: room.id,
+        code: generate_code(INVITE_CODE_LEN),
+        created_by: claims.user_id,
+        created_at: now,
+        expires_at: payload.expires_in.map(|secs| {
+            DateTime::from_millis(
+                now.timestamp_millis()
+                    .saturating_add(i64::try_from(secs).unwrap_or(i64::MAX).saturating_mul(1000)),
+            )
+        }),
+        max_uses: payload.max_uses,
+        uses: 0,
+        revoked: false,
+    };
+
+    // Codes are short, so retry on the rare collision
+    for _ in 0..3 {
+        match collection.insert_one(&invite).await {
+            Ok(_) => {
+                return Ok(Json(InviteResponse {
+                    msg: format!("Share the code to invite people to {}", room.name),
+                    id: invite.id,
+                    code: invite.code,
+                }));
+            }
+            Err(e) if is_duplicate_key_error(&e) => {
+                invite.code = generate_code(INVITE_CODE_LEN);
+            }
+            Err(e) => {
+                println!("Error in creating the invite: {e}");
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error".to_string(),
+                ));
+            }
+        }
+    }
+
+    Err((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Could not create a unique code, try again".to_string(),
+    ))
+}
+
+pub async fn get_room_invites(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<RoomInvite>>, (StatusCode, String)> {
+    let collection: Collection<RoomInvite> = db.collection("room_invite");
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::Invite).await?;
+
+    let invites: Vec<RoomInvite> = collection
+        .find(doc! {"room_id": room.id})
+        .sort(doc! {"created_at": -1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(invites))
+}
+
+pub async fn revoke_invite(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(invite_id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<RoomInvite> = db.collection("room_invite");
+
+    let invite_obj_id = ObjectId::parse_str(&invite_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Invite Id".to_string()))?;
+
+    let invite = collection
+        .find_one(doc! {"_id": invite_obj_id})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Invite Not Found".to_string()))?;
+
+    find_room(
+        &db,
+        &invite.room_id.to_hex(),
+        claims.user_id,
+        Permission::Invite,
+    )
+    .await?;
+
+    collection
+        .update_one(
+            doc! {"_id": invite.id},
+            doc! { "$set": { "revoked": true } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    Ok("The invite is revoked".to_string())
+}
+
+pub async fn join_with_invite(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(code): Path<String>,
+) -> Result<Json<JoinResponse>, (StatusCode, String)> {
+    let collection: Collection<RoomInvite> = db.collection("room_invite");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let invalid_code = (
+        StatusCode::NOT_FOUND,
+        "The invite code is invalid or expired".to_string(),
+    );
+
+    let invite = collection
+        .find_one(doc! {"code": &code})
+        .await
+        .map_err(internal_error)?
+        .ok_or(invalid_code.clone())?;
+
+    let room = room_collection
+        .find_one(doc! {"_id": invite.room_id})
+        .await
+        .map_err(internal_error)?
+        .ok_or(invalid_code.clone())?;
+
+    // The code only stands in for the visibility, the other rules still apply
+    policy::check_can_join(&db, &room, claims.user_id, false).await?;
+
+    // Using the code and checking its limits is one update, so the last use can't be taken twice
+    let now = DateTime::now();
+    collection
+        .find_one_and_update(
+            doc! {
+                "_id": invite.id,
+                "revoked": false,
+                "$and": [
+                    { "$or": [ { "expires_at": null }, { "expires_at": { "$gt": now } } ] },
+                    { "$or": [ { "max_uses": null }, { "$expr": { "$lt": ["$uses", "$max_uses"] } } ] }
+                ]
+            },
+            doc! { "$inc": { "uses": 1 } },
+        )
+        .await
+        .map_err(internal_error)?
+        .ok_or(invalid_code)?;
+
+    add_member(&db, room.id, claims.user_id, RoomRole::Member, false).await?;
+
+    events::emit(
+        &db,
+        room.id,
+        RoomEvent::MemberJoined {
+            user_id: claims.user_id,
+            actor_id: invite.created_by,
+        },
+    );
+
+    Ok(Json(JoinResponse {
+        msg: format!("You have joined {}", room.name),
+        room_id: room.id,
+    }))
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
@@ -0,0 +1,381 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use bson::{doc, oid::ObjectId};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    events::{self, RoomEvent},
+    middleware::auth_middleware::Claims,
+    models::{
+        join_request_model::{JoinRequest, JoinRequestStatus},
+        membership_model::Membership,
+        moderation_model::RestrictionKind,
+        room_model::{Room, RoomRole},
+        user_model::User,
+    },
+    policy::{self, Permission, find_room},
+    utils::db::is_duplicate_key_error,
+};
+
+const MAX_REASON_LEN: usize = 500;
+
+// DTOs
+#[derive(Deserialize, Default)]
+pub struct ReviewRequest {
+    reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct JoinRequests {
+    id: ObjectId,
+    room_id: ObjectId,
+    room_name: String,
+    user_id: ObjectId,
+    // Left out when applicants list their own requests
+    #[serde(skip_serializing_if = "String::is_empty")]
+    user_name: String,
+    status: JoinRequestStatus,
+    created_at: DateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviewed_at: Option<DateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+// Called by join_room for restricted rooms
+pub async fn create_join_request(
+    db: &Database,
+    room: &Room,
+    user_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<JoinRequest> = db.collection("join_request");
+
+    policy::check_in_workspace(db, room, user_id).await?;
+
+    let request = JoinRequest {
+        id: ObjectId::new(),
+        room_id: room.id,
+        user_id,
+        status: JoinRequestStatus::Pending,
+        created_at: DateTime::now(),
+        reviewed_by: None,
+        reviewed_at: None,
+        reason: None,
+    };
+
+    // The partial unique index allows only one pending request per user and room
+    match collection.insert_one(&request).await {
+        Ok(_) => Ok(()),
+        Err(e) if is_duplicate_key_error(&e) => Err((
+            StatusCode::CONFLICT,
+            "You already asked to join this room".to_string(),
+        )),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+pub async fn get_room_join_requests(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<JoinRequests>>, (StatusCode, String)> {
+    let collection: Collection<JoinRequest> = db.collection("join_request");
+    let user_collection: Collection<User> = db.collection("user");
+
+    let room = find_room(
+        &db,
+        &room_id,
+        claims.user_id,
+        Permission::ReviewJoinRequests,
+    )
+    .await?;
+
+    let requests: Vec<JoinRequest> = collection
+        .find(doc! {"room_id": room.id, "status": JoinRequestStatus::Pending.as_str()})
+        .sort(doc! {"created_at": 1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    let user_ids: Vec<ObjectId> = requests.iter().map(|request| request.user_id).collect();
+    let users: Vec<User> = user_collection
+        .find(doc! {"_id": { "$in": user_ids }})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(
+        requests
+            .into_iter()
+            .map(|request| JoinRequests {
+                id: request.id,
+                room_id: room.id,
+                room_name: room.name.clone(),
+                user_id: request.user_id,
+                user_name: users
+                    .iter()
+                    .find(|user| user.id == request.user_id)
+                    .map(|user| user.name.clone())
+                    .unwrap_or_default(),
+                status: request.status,
+                created_at: request.created_at,
+                reviewed_at: request.reviewed_at,
+                reason: request.reason,
+            })
+            .collect(),
+    ))
+}
+
+// The applicant's own requests with their status
+pub async fn get_my_join_requests(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+) -> Result<Json<Vec<JoinRequests>>, (StatusCode, String)> {
+    let collection: Collection<JoinRequest> = db.collection("join_request");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let requests: Vec<JoinRequest> = collection
+        .find(doc! {"user_id": claims.user_id})
+        .sort(doc! {"created_at": -1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    let room_ids: Vec<ObjectId> = requests.iter().map(|request| request.room_id).collect();
+    let rooms: Vec<Room> = room_collection
+        .find(doc! {"_id": { "$in": room_ids }})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(
+        requests
+            .into_iter()
+            .map(|request| JoinRequests {
+                id: request.id,
+                room_id: request.room_id,
+                room_name: rooms
+                    .iter()
+                    .find(|room| room.id == request.room_id)
+                    .map(|room| room.name.clone())
+                    .unwrap_or_default(),
+                user_id: request.user_id,
+                user_name: String::new(),
+                status: request.status,
+                created_at: request.created_at,
+                reviewed_at: request.reviewed_at,
+                reason: request.reason,
+            })
+            .collect(),
+    ))
+}
+
+pub async fn approve_join_request(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, id)): Path<(String, String)>,
+    payload: Option<Json<ReviewRequest>>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<JoinRequest> = db.collection("join_request");
+    let room_collection: Collection<Room> = db.collection("room");
+    let membership_collection: Collection<Membership> = db.collection("membership");
+
+    let reason = review_reason(payload)?;
+    let (request, room) = find_pending_request(&db, &room_id, &id, claims.user_id).await?;
+
+    policy::check_not_archived(&room)?;
+    policy::check_not_restricted(&db, room.id, request.user_id, RestrictionKind::Ban).await?;
+    // The applicant may have left the workspace since they asked
+    policy::check_in_workspace(&db, &room, request.user_id)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::FORBIDDEN,
+                "The user is no longer part of the room's workspace".to_string(),
+            )
+        })?;
+
+    // Closing the request and adding the member happen together or not at all
+    let mut session = db.client().start_session().await.map_err(internal_error)?;
+    session.start_transaction().await.map_err(internal_error)?;
+
+    let reviewed = collection
+        .update_one(
+            doc! {"_id": request.id, "status": JoinRequestStatus::Pending.as_str()},
+            review_update(JoinRequestStatus::Approved, claims.user_id, reason),
+        )
+        .session(&mut session)
+        .await
+        .map_err(internal_error)?;
+
+    if reviewed.matched_count == 0 {
+        return Err((
+            StatusCode::CONFLICT,
+            "The request was already reviewed".to_string(),
+        ));
+    }
+
+    // Same as add_member, inside the transaction
+    match membership_collection
+        .insert_one(Membership::new(
+            room.id,
+            request.user_id,
+            RoomRole::Member,
+            false,
+        ))
+        .session(&mut session)
+        .await
+    {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key_error(&e) => {
+            return Err((
+                StatusCode::CONFLICT,
+                "The user is already in the room".to_string(),
+            ));
+        }
+        Err(e) => return Err(internal_error(e)),
+    }
+
+    room_collection
+        .update_one(
+            doc! {"_id": room.id},
+            doc! { "$inc": { "member_count": 1 } },
+        )
+        .session(&mut session)
+        .await
+        .map_err(internal_error)?;
+
+    session.commit_transaction().await.map_err(internal_error)?;
+
+    events::emit(
+        &db,
+        room.id,
+        RoomEvent::MemberJoined {
+            user_id: request.user_id,
+            actor_id: claims.user_id,
+        },
+    );
+
+    Ok("The request was approved".to_string())
+}
+
+pub async fn reject_join_request(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, id)): Path<(String, String)>,
+    payload: Option<Json<ReviewRequest>>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<JoinRequest> = db.collection("join_request");
+
+    let reason = review_reason(payload)?;
+    let (request, _) = find_pending_request(&db, &room_id, &id, claims.user_id).await?;
+
+    let reviewed = collection
+        .update_one(
+            doc! {"_id": request.id, "status": JoinRequestStatus::Pending.as_str()},
+            review_update(JoinRequestStatus::Rejected, claims.user_id, reason),
+        )
+        .await
+        .map_err(internal_error)?;
+
+    if reviewed.matched_count == 0 {
+        return Err((
+            StatusCode::CONFLICT,
+            "The request was already reviewed".to_string(),
+        ));
+    }
+
+    Ok("The request was rejected".to_string())
+}
+
+fn review_reason(
+    payload: Option<Json<ReviewRequest>>,
+) -> Result<Option<String>, (StatusCode, String)> {
+    let reason = payload
+        .map(|Json(review)| review)
+        .unwrap_or_default()
+        .reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+
+    if reason
+        .as_ref()
+        .is_some_and(|reason| reason.chars().count() > MAX_REASON_LEN)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The reason can be at most {MAX_REASON_LEN} characters"),
+        ));
+    }
+
+    Ok(reason)
+}
+
+fn review_update(
+    status: JoinRequestStatus,
+    reviewer: ObjectId,
+    reason: Option<String>,
+) -> bson::Document {
+    let mut set = doc! {
+        "status": status.as_str(),
+        "reviewed_by": reviewer,
+        "reviewed_at": DateTime::now(),
+    };
+    if let Some(reason) = reason {
+        set.insert("reason", reason);
+    }
+    doc! { "$set": set }
+}
+
+// Requests are looked up within the room in the path, whose membership was checked on the way in
+async fn find_pending_request(
+    db: &Database,
+    room_id: &str,
+    id: &str,
+    user_id: ObjectId,
+) -> Result<(JoinRequest, Room), (StatusCode, String)> {
+    let collection: Collection<JoinRequest> = db.collection("join_request");
+
+    let room = find_room(db, room_id, user_id, Permission::ReviewJoinRequests).await?;
+
+    let request_obj_id = ObjectId::parse_str(id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Request Id".to_string()))?;
+
+    let request = collection
+        .find_one(doc! {"_id": request_obj_id, "room_id": room.id})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Request Not Found".to_string()))?;
+
+    if request.status != JoinRequestStatus::Pending {
+        return Err((
+            StatusCode::CONFLICT,
+            "The request was already reviewed".to_string(),
+        ));
+    }
+
+    Ok((request, room))
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
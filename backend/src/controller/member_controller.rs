@@ -0,0 +1,379 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use bson::{Document, doc, oid::ObjectId};
+use futures_util::{FutureExt, TryStreamExt};
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    middleware::{auth_middleware::Claims, presence_middleware::ONLINE_WINDOW_MILLIS},
+    models::{
+        membership_model::{Membership, NotificationLevel},
+        message_model::Message,
+        room_model::{Room, RoomRole},
+    },
+    policy::{Permission, find_room},
+    utils::{
+        db::is_duplicate_key_error,
+        query::{SortValue, after_cursor, encode_cursor, escape_regex},
+    },
+};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 100;
+const MAX_PREFIX_LEN: usize = 100;
+const LISTING: &str = "members";
+
+// DTOs
+#[derive(Deserialize)]
+pub struct MemberQuery {
+    // Start of the name, for @mention autocomplete
+    prefix: Option<String>,
+    cursor: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    Online,
+    Offline,
+}
+
+#[derive(Serialize)]
+pub struct Member {
+    id: ObjectId,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar: Option<String>,
+    role: RoomRole,
+    joined_at: DateTime,
+    // Bots have no presence
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence: Option<Presence>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen_at: Option<DateTime>,
+    is_bot: bool,
+}
+
+#[derive(Serialize)]
+pub struct MemberPage {
+    members: Vec<Member>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MembershipRequest {
+    notifications: Option<NotificationLevel>,
+    // Marks everything up to this message as read
+    last_read_message_id: Option<String>,
+}
+
+// A membership with the profile of the user or bot, as read by the pipeline
+#[derive(Deserialize)]
+struct Profile {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    user_id: ObjectId,
+    role: RoomRole,
+    joined_at: DateTime,
+    is_bot: bool,
+    name: String,
+    avatar: Option<String>,
+    last_seen_at: Option<DateTime>,
+    sort_name: String,
+}
+
+pub async fn get_room_members(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+    Query(query): Query<MemberQuery>,
+) -> Result<Json<MemberPage>, (StatusCode, String)> {
+    let collection: Collection<Membership> = db.collection("membership");
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::ViewMembers).await?;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let prefix = query
+        .prefix
+        .map(|prefix| prefix.trim().trim_start_matches('@').to_string())
+        .filter(|prefix| !prefix.is_empty());
+    if prefix
+        .as_ref()
+        .is_some_and(|prefix| prefix.chars().count() > MAX_PREFIX_LEN)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The prefix can be at most {MAX_PREFIX_LEN} characters"),
+        ));
+    }
+
+    // Bots in the room live in their own collection
+    let mut pipeline = vec![
+        doc! { "$match": { "room_id": room.id } },
+        doc! { "$lookup": {
+            "from": "user", "localField": "user_id", "foreignField": "_id", "as": "user"
+        } },
+        doc! { "$lookup": {
+            "from": "bot", "localField": "user_id", "foreignField": "_id", "as": "bot"
+        } },
+        doc! { "$set": {
+            "profile": { "$first": { "$concatArrays": ["$user", "$bot"] } }
+        } },
+        // Leftovers of deleted users have no profile
+        doc! { "$match": { "profile": { "$exists": true } } },
+        doc! { "$project": {
+            "user_id": 1,
+            "role": 1,
+            "joined_at": 1,
+            "is_bot": 1,
+            "name": "$profile.name",
+            "avatar": "$profile.avatar",
+            "last_seen_at": "$profile.last_seen_at",
+            "sort_name": { "$toLower": "$profile.name" },
+        } },
+    ];
+
+    if let Some(prefix) = &prefix {
+        pipeline.push(doc! { "$match": {
+            "name": { "$regex": format!("^{}", escape_regex(prefix)), "$options": "i" }
+        } });
+    }
+
+    if let Some(cursor) = &query.cursor {
+        let after = after_cursor(cursor, LISTING, "sort_name", SortValue::Text, true)?;
+        pipeline.push(doc! { "$match": after });
+    }
+
+    pipeline.extend([
+        doc! { "$sort": { "sort_name": 1, "_id": 1 } },
+        // One extra to know if there is a next page
+        doc! { "$limit": i64::from(limit) + 1 },
+    ]);
+
+    let mut profiles: Vec<Profile> = collection
+        .aggregate(pipeline)
+        .with_type::<Profile>()
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    let next_cursor = if profiles.len() > limit as usize {
+        profiles.truncate(limit as usize);
+        profiles
+            .last()
+            .map(|profile| encode_cursor(LISTING, profile.sort_name.clone().into(), profile.id))
+    } else {
+        None
+    };
+
+    let online_since = DateTime::now().timestamp_millis() - ONLINE_WINDOW_MILLIS;
+
+    let members = profiles
+        .into_iter()
+        .map(|profile| Member {
+            id: profile.user_id,
+            // The room decides who owns it
+            role: if profile.user_id == room.owner {
+                RoomRole::Owner
+            } else {
+                profile.role
+            },
+            joined_at: profile.joined_at,
+            presence: (!profile.is_bot).then(|| match profile.last_seen_at {
+                Some(seen) if seen.timestamp_millis() >= online_since => Presence::Online,
+                _ => Presence::Offline,
+            }),
+            last_seen_at: profile.last_seen_at,
+            name: profile.name,
+            avatar: profile.avatar,
+            is_bot: profile.is_bot,
+        })
+        .collect();
+
+    Ok(Json(MemberPage {
+        members,
+        next_cursor,
+    }))
+}
+
+// The caller's own notification level and read position
+pub async fn update_membership(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+    Json(payload): Json<MembershipRequest>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<Membership> = db.collection("membership");
+    let message_collection: Collection<Message> = db.collection("message");
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::ViewMembers).await?;
+
+    let mut set = Document::new();
+
+    if let Some(level) = payload.notifications {
+        set.insert("notifications", level.as_str());
+    }
+
+    if let Some(message_id) = payload.last_read_message_id {
+        let message_obj_id = ObjectId::parse_str(&message_id)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Message Id".to_string()))?;
+
+        let message = message_collection
+            .find_one(doc! {"_id": message_obj_id, "room_id": room.id})
+            .await
+            .map_err(internal_error)?
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                "The message is not in this room".to_string(),
+            ))?;
+
+        set.insert("last_read_message_id", message.id);
+        set.insert("last_read_at", DateTime::now());
+    }
+
+    if set.is_empty() {
+        return Ok("Nothing to update".to_string());
+    }
+
+    collection
+        .update_one(
+            doc! {"room_id": room.id, "user_id": claims.user_id},
+            doc! { "$set": set },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    Ok("Your membership was updated".to_string())
+}
+
+// Adds the user or bot to the room, false when they already were a member
+pub async fn add_member(
+    db: &Database,
+    room_id: ObjectId,
+    user_id: ObjectId,
+    role: RoomRole,
+    is_bot: bool,
+) -> Result<bool, (StatusCode, String)> {
+    let collection: Collection<Membership> = db.collection("membership");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let membership = Membership::new(room_id, user_id, role, is_bot);
+
+    // The membership and the member count change together
+    let mut session = db.client().start_session().await.map_err(internal_error)?;
+    let result = session
+        .start_transaction()
+        .and_run(
+            (collection, room_collection, membership),
+            |session, (collection, room_collection, membership)| {
+                async move {
+                    collection.insert_one(&*membership).session(&mut *session).await?;
+                    room_collection
+                        .update_one(
+                            doc! {"_id": membership.room_id},
+                            doc! { "$inc": { "member_count": 1 } },
+                        )
+                        .session(session)
+                        .await?;
+                    Ok(())
+                }
+                .boxed()
+            },
+        )
+        .await;
+
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) if is_duplicate_key_error(&e) => Ok(false),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+// False when the user was not a member
+pub async fn remove_member(
+    db: &Database,
+    room_id: ObjectId,
+    user_id: ObjectId,
+) -> Result<bool, (StatusCode, String)> {
+    let collection: Collection<Membership> = db.collection("membership");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let mut session = db.client().start_session().await.map_err(internal_error)?;
+    session
+        .start_transaction()
+        .and_run(
+            (collection, room_collection),
+            |session, (collection, room_collection)| {
+                async move {
+                    let result = collection
+                        .delete_one(doc! {"room_id": room_id, "user_id": user_id})
+                        .session(&mut *session)
+                        .await?;
+
+                    if result.deleted_count == 0 {
+                        return Ok(false);
+                    }
+
+                    room_collection
+                        .update_one(
+                            doc! {"_id": room_id},
+                            doc! { "$inc": { "member_count": -1 } },
+                        )
+                        .session(session)
+                        .await?;
+                    Ok(true)
+                }
+                .boxed()
+            },
+        )
+        .await
+        .map_err(internal_error)
+}
+
+// The member who takes over when the owner is gone, the longest-standing one among the
+// admins, moderators and members. Bots and read-only members never inherit the room
+pub async fn successor(
+    db: &Database,
+    room: &Room,
+) -> Result<Option<ObjectId>, (StatusCode, String)> {
+    let collection: Collection<Membership> = db.collection("membership");
+
+    let membership = collection
+        .find_one(doc! {
+            "room_id": room.id,
+            "user_id": { "$ne": room.owner },
+            "role": { "$in": [
+                RoomRole::Admin.as_str(),
+                RoomRole::Moderator.as_str(),
+                RoomRole::Member.as_str(),
+            ] },
+            "is_bot": false,
+        })
+        .sort(doc! {"joined_at": 1})
+        .await
+        .map_err(internal_error)?;
+
+    Ok(membership.map(|membership| membership.user_id))
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
@@ -1,7 +1,8 @@
 use axum::{
     Json,
     extract::{Path, State},
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
+    response::{IntoResponse, Response},
 };
 use bson::{doc, oid::ObjectId};
 use futures_util::TryStreamExt;
@@ -12,19 +13,44 @@ use std::sync::Arc;
 
 // Crates
 use crate::{
+    commands::{CommandContext, CommandOutcome, dispatch, parse_command},
+    controller::group_dm_controller::{check_group_member, find_group_dm},
+    events::{self, RoomEvent},
     middleware::auth_middleware::Claims,
-    models::{message_model::Message, room_model::Room, user_model::User},
+    models::{
+        group_dm_model::GroupDm,
+        membership_model::Membership,
+        message_model::{BotSender, ForwardedFrom, Message, PinnedBy, ReplySnapshot},
+        moderation_model::RestrictionKind,
+        nonce_model::MessageNonce,
+        poll_model::Poll,
+        room_model::{Room, RoomRole},
+        user_model::User,
+    },
+    policy::{self, Permission, SlowMode},
+    utils::db::is_duplicate_key_error,
 };
 
 // DTOs
 #[derive(Deserialize)]
 pub struct MessageRequest {
     content: String,
+    // Client generated id, resending the same one returns the original message
+    client_nonce: Option<String>,
+    // Id of an earlier message in the same conversation to quote
+    reply_to: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ForwardRequest {
+    destination_id: String,
 }
 
 #[derive(Serialize)]
 pub struct MessageResponse {
     msg: String,
+    // Empty when a slash command only answered the caller
+    #[serde(skip_serializing_if = "String::is_empty")]
     id: String,
 }
 
@@ -33,6 +59,12 @@ pub struct GetRoomMessages {
     sender_id: ObjectId,
     room_id: Option<ObjectId>,
     content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<ReplySnapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    poll: Option<Poll>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bot: Option<BotSender>,
 }
 
 #[derive(Serialize)]
@@ -40,15 +72,60 @@ pub struct GetDMMessages {
     sender_id: ObjectId,
     receiver_id: Option<ObjectId>,
     content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<ReplySnapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bot: Option<BotSender>,
+}
+
+// Errors of the handlers that post, slow mode also tells the client when to retry
+pub struct PostError {
+    status: StatusCode,
+    msg: String,
+    retry_after: Option<u64>,
+}
+
+impl From<(StatusCode, String)> for PostError {
+    fn from((status, msg): (StatusCode, String)) -> Self {
+        PostError {
+            status,
+            msg,
+            retry_after: None,
+        }
+    }
 }
 
+impl IntoResponse for PostError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, self.msg).into_response();
+        if let Some(secs) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+// Longest quote kept in a reply snapshot
+const QUOTE_PREVIEW_LEN: usize = 200;
+
+// How long a pending nonce holds off retries before they may take it over
+const NONCE_CLAIM_SECS: i64 = 60;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RecentChat {
     pub chat_id: ObjectId,
-    pub chat_type: String, // "user" or "room"
+    pub chat_type: String, // "user", "room" or "group"
     pub name: String,
     pub last_message: String,
     pub timestamp: DateTime,
+    // Archived rooms come after every active chat
+    #[serde(default)]
+    pub archived: bool,
+    // Direct chats with a bot account
+    #[serde(default)]
+    pub bot: bool,
 }
 
 pub async fn send_message(
@@ -56,18 +133,20 @@ pub async fn send_message(
     claims: Claims,
     Path(id): Path<String>,
     Json(payload): Json<MessageRequest>,
-) -> Result<Json<MessageResponse>, (StatusCode, String)> {
+) -> Result<Json<MessageResponse>, PostError> {
     let user_collection: Collection<User> = db.collection("user");
     let message_collection: Collection<Message> = db.collection("message");
     let room_collection: Collection<Room> = db.collection("room");
+    let user_obj_id: ObjectId = claims.user_id;
 
-    let user_obj_id: ObjectId = claims.user_id.clone();
+    claims.require_scope("messages:write")?;
 
     if id.is_empty() || payload.content.is_empty() {
         return Err((
             StatusCode::BAD_REQUEST,
             "The fields are required".to_string(),
-        ));
+        )
+            .into());
     }
 
     let receiver_obj_id = ObjectId::parse_str(id)
@@ -78,59 +157,425 @@ pub async fn send_message(
     };
 
     enum Receiver {
-        Room(Room),
+        Room(Box<Room>),
+        Group(GroupDm),
         User(User),
     }
 
     let receiver = match room_collection.find_one(filter.clone()).await {
-        Ok(Some(room)) => Receiver::Room(room),
-        Ok(None) => match user_collection.find_one(filter).await {
-            Ok(Some(user)) => Receiver::User(user),
-            Ok(None) => return Err((StatusCode::NOT_FOUND, "Not Found".to_string())),
-            Err(e) => {
-                println!("Some error occurred: {e}");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internal Server Error".to_string(),
-                ));
-            }
+        Ok(Some(room)) => Receiver::Room(Box::new(room)),
+        Ok(None) => match find_group_dm(&db, receiver_obj_id).await? {
+            Some(group) => Receiver::Group(group),
+            None => match user_collection.find_one(filter).await {
+                Ok(Some(user)) => Receiver::User(user),
+                Ok(None) => return Err((StatusCode::NOT_FOUND, "Not Found".to_string()).into()),
+                Err(e) => {
+                    println!("Some error occurred: {e}");
+                    return Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Internal Server Error".to_string(),
+                    )
+                        .into());
+                }
+            },
         },
         Err(e) => {
             println!("Some error occured: {e}");
             return Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal Server Error".to_string(),
-            ));
+            )
+                .into());
+        }
+    };
+
+    let (receiver_id, room_id, group_id, role) = match &receiver {
+        Receiver::User(user) => (Some(user.id), None, None, None),
+        Receiver::Group(group) => {
+            check_group_member(group, user_obj_id)?;
+            (None, None, Some(group.id), None)
+        }
+        Receiver::Room(room) => {
+            let role = policy::check(&db, room, user_obj_id, Permission::PostMessages).await?;
+            policy::check_not_restricted(&db, room.id, user_obj_id, RestrictionKind::Mute).await?;
+            (None, Some(room.id), None, Some(role))
         }
     };
 
-    let (receiver_id, room_id) = match &receiver {
-        Receiver::User(user) => (Some(user.id.clone()), None),
-        Receiver::Room(room) => (None, Some(room.id.clone())),
+    // The whole request is checked before anything runs, a rejected send leaves nothing behind
+    let reply_to = match &payload.reply_to {
+        Some(reply_id) => {
+            Some(reply_snapshot(&db, reply_id, user_obj_id, receiver_id, room_id, group_id).await?)
+        }
+        None => None,
     };
 
     let bson_datetime = DateTime::now();
+    let message_id = ObjectId::new();
+
+    // Claimed before any command runs, so a retried send doesn't run it again
+    if let Some(nonce) = &payload.client_nonce
+        && let NonceClaim::Replay(response) =
+            claim_nonce(&db, user_obj_id, nonce, message_id, room_id).await?
+    {
+        return Ok(Json(response));
+    }
+
+    // Taken after the nonce, a replayed send returns its result without waiting
+    let mut slot = None;
+    if let (Receiver::Room(room), Some(role)) = (&receiver, role) {
+        match take_slow_mode(&db, room, user_obj_id, role).await {
+            Ok(taken) => slot = taken,
+            Err(e) => {
+                abandon_send(&db, user_obj_id, payload.client_nonce.as_deref(), None).await;
+                return Err(e);
+            }
+        }
+    }
+    let slot = slot.as_ref();
+
+    // Slash commands run before the message is stored, unknown ones are sent as plain text
+    let mut content = payload.content;
+    let mut sender_id = user_obj_id;
+    let mut bot = bot_sender(&claims);
+    if let Some((name, args)) = parse_command(&content) {
+        let ctx = CommandContext {
+            db: &db,
+            caller: user_obj_id,
+            room: match &receiver {
+                Receiver::Room(room) => Some(room.as_ref()),
+                Receiver::Group(_) | Receiver::User(_) => None,
+            },
+            args,
+            nonce: payload.client_nonce.as_deref(),
+        };
+
+        let outcome = match dispatch(name, ctx).await.transpose() {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                abandon_send(&db, user_obj_id, payload.client_nonce.as_deref(), slot).await;
+                return Err(e.into());
+            }
+        };
+
+        match outcome {
+            Some(CommandOutcome::Post(text)) => content = text,
+            Some(CommandOutcome::PostAs {
+                bot: integration,
+                content: text,
+            }) => {
+                sender_id = integration.bot_id;
+                bot = Some(integration);
+                content = text;
+            }
+            Some(CommandOutcome::Reply(text)) => {
+                // Kept with the nonce, a retry gets the same answer without running it again.
+                // Nothing was posted, so the slow mode turn is given back
+                if let Some(nonce) = &payload.client_nonce {
+                    finish_nonce(&db, user_obj_id, nonce, Some(&text)).await;
+                }
+                if let Some(slot) = slot {
+                    slot.release(&db).await;
+                }
+                return Ok(Json(MessageResponse {
+                    msg: text,
+                    id: String::new(),
+                }));
+            }
+            None => {}
+        }
+    }
 
     let new_message = Message {
-        id: ObjectId::new(),
-        sender_id: user_obj_id,
+        id: message_id,
+        sender_id,
         receiver_id,
         room_id,
-        content: payload.content,
+        group_id,
+        content,
         timestamp: bson_datetime,
+        forwarded_from: None,
+        reply_to,
+        poll: None,
+        bot,
+        pinned: None,
     };
 
-    match message_collection.insert_one(new_message).await {
+    match message_collection.insert_one(&new_message).await {
         Ok(message_sent) => {
-            return Ok(Json(MessageResponse {
+            if let Some(nonce) = &payload.client_nonce {
+                finish_nonce(&db, user_obj_id, nonce, None).await;
+            }
+            if let Some(room_id) = room_id {
+                events::emit(
+                    &db,
+                    room_id,
+                    RoomEvent::MessageCreated(Box::new(new_message)),
+                );
+            }
+            Ok(Json(MessageResponse {
                 msg: "Message was sent Successfully".to_string(),
                 id: message_sent
                     .inserted_id
                     .as_object_id()
                     .map(|oid| oid.to_hex())
                     .unwrap_or_default(),
+            }))
+        }
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            abandon_send(&db, user_obj_id, payload.client_nonce.as_deref(), slot).await;
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+                .into())
+        }
+    }
+}
+
+// What claiming a client nonce found
+enum NonceClaim {
+    // The nonce is new, the send goes ahead
+    Claimed,
+    // An earlier send with the nonce went through, its result is returned again
+    Replay(MessageResponse),
+}
+
+// The unique index on (sender_id, nonce) lets only one retry claim the nonce. The claim
+// stays pending until the message is stored, and a retry only gets the message id back
+// once the message exists
+async fn claim_nonce(
+    db: &Database,
+    sender_id: ObjectId,
+    nonce: &str,
+    message_id: ObjectId,
+    room_id: Option<ObjectId>,
+) -> Result<NonceClaim, (StatusCode, String)> {
+    let nonce_collection: Collection<MessageNonce> = db.collection("message_nonce");
+    let message_collection: Collection<Message> = db.collection("message");
+
+    if nonce.is_empty() || nonce.len() > 64 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The client nonce must be 1 to 64 characters".to_string(),
+        ));
+    }
+
+    let still_sending = (
+        StatusCode::CONFLICT,
+        "The message is still being sent, retry again".to_string(),
+    );
+
+    // A claim left behind by a send that died gets taken over once
+    for _ in 0..2 {
+        let record = MessageNonce {
+            id: ObjectId::new(),
+            sender_id,
+            nonce: nonce.to_string(),
+            message_id,
+            room_id,
+            pending: true,
+            reply: None,
+            created_at: DateTime::now(),
+        };
+
+        let e = match nonce_collection.insert_one(&record).await {
+            Ok(_) => return Ok(NonceClaim::Claimed),
+            Err(e) => e,
+        };
+        if !is_duplicate_key_error(&e) {
+            return Err(internal_error(e));
+        }
+
+        let existing = nonce_collection
+            .find_one(doc! { "sender_id": sender_id, "nonce": nonce })
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| still_sending.clone())?;
+
+        if !existing.pending
+            && let Some(reply) = existing.reply
+        {
+            return Ok(NonceClaim::Replay(MessageResponse {
+                msg: reply,
+                id: String::new(),
             }));
         }
+
+        // The first send may have stored its message without getting to mark the nonce
+        if !existing.pending
+            || message_collection
+                .find_one(doc! {"_id": existing.message_id})
+                .await
+                .map_err(internal_error)?
+                .is_some()
+        {
+            return Ok(NonceClaim::Replay(MessageResponse {
+                msg: "Message was sent Successfully".to_string(),
+                id: existing.message_id.to_hex(),
+            }));
+        }
+
+        let claimed_millis =
+            DateTime::now().timestamp_millis() - existing.created_at.timestamp_millis();
+        if claimed_millis < NONCE_CLAIM_SECS * 1000 {
+            return Err(still_sending);
+        }
+
+        nonce_collection
+            .delete_one(doc! {"_id": existing.id, "pending": true})
+            .await
+            .map_err(internal_error)?;
+    }
+
+    Err(still_sending)
+}
+
+// The message is stored, or the command answered with a reply, retries get the same
+// result from now on
+async fn finish_nonce(db: &Database, sender_id: ObjectId, nonce: &str, reply: Option<&str>) {
+    let nonce_collection: Collection<MessageNonce> = db.collection("message_nonce");
+
+    let mut set = doc! { "pending": false };
+    if let Some(reply) = reply {
+        set.insert("reply", reply);
+    }
+
+    // A retry also finds the stored message itself, so a failure here only costs a lookup
+    if let Err(e) = nonce_collection
+        .update_one(
+            doc! { "sender_id": sender_id, "nonce": nonce },
+            doc! { "$set": set },
+        )
+        .await
+    {
+        println!("Failed to mark the nonce as sent: {e}");
+    }
+}
+
+// Frees the nonce of a send that failed, so the client's retry can go through
+async fn release_nonce(db: &Database, sender_id: ObjectId, nonce: &str) {
+    let nonce_collection: Collection<MessageNonce> = db.collection("message_nonce");
+
+    if let Err(e) = nonce_collection
+        .delete_one(doc! { "sender_id": sender_id, "nonce": nonce, "pending": true })
+        .await
+    {
+        println!("Failed to release the nonce: {e}");
+    }
+}
+
+// Gives back the nonce and slow mode turn of a send that didn't go through, so a retry can
+async fn abandon_send(
+    db: &Database,
+    sender_id: ObjectId,
+    nonce: Option<&str>,
+    slot: Option<&SlowModeTurn>,
+) {
+    if let Some(nonce) = nonce {
+        release_nonce(db, sender_id, nonce).await;
+    }
+    if let Some(slot) = slot {
+        slot.release(db).await;
+    }
+}
+
+// A slow mode turn taken for a post, given back when the post doesn't happen
+pub struct SlowModeTurn {
+    room_id: ObjectId,
+    sender_id: ObjectId,
+    taken_at: DateTime,
+}
+
+impl SlowModeTurn {
+    pub async fn release(&self, db: &Database) {
+        policy::release_slow_mode(db, self.room_id, self.sender_id, self.taken_at).await;
+    }
+}
+
+// Every way of posting into a room goes through here, webhooks pass their own id
+pub async fn take_slow_mode(
+    db: &Database,
+    room: &Room,
+    sender_id: ObjectId,
+    role: RoomRole,
+) -> Result<Option<SlowModeTurn>, PostError> {
+    match policy::take_slow_mode(db, room, sender_id, role).await? {
+        SlowMode::Exempt => Ok(None),
+        SlowMode::Taken(taken_at) => Ok(Some(SlowModeTurn {
+            room_id: room.id,
+            sender_id,
+            taken_at,
+        })),
+        SlowMode::Wait(secs) => Err(PostError {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            msg: format!("Slow mode is on, you can post again in {secs} seconds"),
+            retry_after: Some(secs),
+        }),
+    }
+}
+
+// Marks messages sent with a bot token
+fn bot_sender(claims: &Claims) -> Option<BotSender> {
+    claims.bot.as_ref().map(|bot| BotSender {
+        bot_id: claims.user_id,
+        name: bot.name.clone(),
+    })
+}
+
+// Builds the quote for a reply, the quoted message must belong to the same conversation
+async fn reply_snapshot(
+    db: &Database,
+    reply_id: &str,
+    sender_id: ObjectId,
+    receiver_id: Option<ObjectId>,
+    room_id: Option<ObjectId>,
+    group_id: Option<ObjectId>,
+) -> Result<ReplySnapshot, (StatusCode, String)> {
+    let message_collection: Collection<Message> = db.collection("message");
+    let user_collection: Collection<User> = db.collection("user");
+
+    let reply_obj_id = ObjectId::parse_str(reply_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Reply Id".to_string()))?;
+
+    let quoted = message_collection
+        .find_one(doc! {"_id": reply_obj_id})
+        .await
+        .map_err(|e| {
+            println!("Some error occurred: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            "The quoted message was not found".to_string(),
+        ))?;
+
+    let same_conversation = match (room_id, group_id) {
+        (Some(room_id), _) => quoted.room_id == Some(room_id),
+        (None, Some(group_id)) => quoted.group_id == Some(group_id),
+        (None, None) => {
+            (quoted.sender_id == sender_id && quoted.receiver_id == receiver_id)
+                || (Some(quoted.sender_id) == receiver_id && quoted.receiver_id == Some(sender_id))
+        }
+    };
+
+    if !same_conversation {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You can only reply to a message in the same conversation".to_string(),
+        ));
+    }
+
+    let sender_name = match user_collection
+        .find_one(doc! {"_id": quoted.sender_id})
+        .await
+    {
+        Ok(Some(user)) => user.name,
+        Ok(None) => "Deleted User".to_string(),
         Err(e) => {
             println!("Some error occurred: {e}");
             return Err((
@@ -138,19 +583,182 @@ pub async fn send_message(
                 "Internal Server Error".to_string(),
             ));
         }
+    };
+
+    Ok(ReplySnapshot {
+        message_id: quoted.id,
+        sender_id: quoted.sender_id,
+        sender_name,
+        content: quoted.content.chars().take(QUOTE_PREVIEW_LEN).collect(),
+    })
+}
+
+pub async fn forward_message(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+    Json(payload): Json<ForwardRequest>,
+) -> Result<Json<MessageResponse>, PostError> {
+    let user_collection: Collection<User> = db.collection("user");
+    let message_collection: Collection<Message> = db.collection("message");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let user_obj_id = claims.user_id;
+
+    claims.require_scope("messages:write")?;
+
+    let message_obj_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Message Id".to_string()))?;
+    let destination_obj_id = ObjectId::parse_str(&payload.destination_id).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            "Invalid Destination Id".to_string(),
+        )
+    })?;
+
+    let source = match message_collection
+        .find_one(doc! {"_id": message_obj_id})
+        .await
+    {
+        Ok(Some(message)) => message,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "Message Not Found".to_string()).into()),
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+                .into());
+        }
+    };
+
+    // Step 1: The caller must be able to read the source message
+    let can_read = match (source.room_id, source.group_id) {
+        (Some(room_id), _) => policy::is_member(&db, room_id, user_obj_id).await?,
+        (None, Some(group_id)) => find_group_dm(&db, group_id)
+            .await?
+            .is_some_and(|group| group.members.contains(&user_obj_id)),
+        (None, None) => source.sender_id == user_obj_id || source.receiver_id == Some(user_obj_id),
+    };
+
+    if !can_read {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You can't read the message you are forwarding".to_string(),
+        )
+            .into());
+    }
+
+    // Step 2: The caller must be able to post to the destination
+    let (receiver_id, room_id, group_id, slot) = match room_collection
+        .find_one(doc! {"_id": destination_obj_id})
+        .await
+    {
+        Ok(Some(room)) => {
+            let role = policy::check(&db, &room, user_obj_id, Permission::PostMessages).await?;
+            policy::check_not_restricted(&db, room.id, user_obj_id, RestrictionKind::Mute).await?;
+            let slot = take_slow_mode(&db, &room, user_obj_id, role).await?;
+            (None, Some(room.id), None, slot)
+        }
+        Ok(None) => match find_group_dm(&db, destination_obj_id).await? {
+            Some(group) => {
+                check_group_member(&group, user_obj_id)?;
+                (None, None, Some(group.id), None)
+            }
+            None => match user_collection
+                .find_one(doc! {"_id": destination_obj_id})
+                .await
+            {
+                Ok(Some(user)) => (Some(user.id), None, None, None),
+                Ok(None) => {
+                    return Err((StatusCode::NOT_FOUND, "Destination Not Found".to_string()).into());
+                }
+                Err(e) => {
+                    println!("Some error occurred: {e}");
+                    return Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Internal Server Error".to_string(),
+                    )
+                        .into());
+                }
+            },
+        },
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+                .into());
+        }
+    };
+
+    // Step 3: Copy the message, pointing back at the source and its original author
+    let original_sender = source
+        .forwarded_from
+        .as_ref()
+        .map(|forwarded| forwarded.sender_id)
+        .unwrap_or(source.sender_id);
+
+    let forwarded_message = Message {
+        id: ObjectId::new(),
+        sender_id: user_obj_id,
+        receiver_id,
+        room_id,
+        group_id,
+        content: source.content,
+        timestamp: DateTime::now(),
+        forwarded_from: Some(ForwardedFrom {
+            message_id: source.id,
+            sender_id: original_sender,
+        }),
+        reply_to: None,
+        poll: None,
+        bot: bot_sender(&claims),
+        pinned: None,
+    };
+
+    match message_collection.insert_one(&forwarded_message).await {
+        Ok(_) => {
+            if let Some(room_id) = room_id {
+                events::emit(
+                    &db,
+                    room_id,
+                    RoomEvent::MessageCreated(Box::new(forwarded_message.clone())),
+                );
+            }
+            Ok(Json(MessageResponse {
+                msg: "Message was forwarded Successfully".to_string(),
+                id: forwarded_message.id.to_hex(),
+            }))
+        }
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            if let Some(slot) = &slot {
+                slot.release(&db).await;
+            }
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+                .into())
+        }
     }
 }
 
 pub async fn get_messages_by_room_id(
     State(db): State<Arc<Database>>,
+    claims: Claims,
     Path(id): Path<String>,
 ) -> Result<Json<Vec<GetRoomMessages>>, (StatusCode, String)> {
     let collection: Collection<Message> = db.collection("message");
-    let room_obj_id = ObjectId::parse_str(id)
-        .map_err(|_| (StatusCode::NOT_FOUND, "Wrong Room Id".to_string()))?;
+
+    claims.require_scope("messages:read")?;
+
+    let room = policy::find_room(&db, &id, claims.user_id, Permission::ReadMessages).await?;
 
     let filter = doc! {
-        "room_id": &room_obj_id
+        "room_id": room.id
     };
 
     let mut cursor = match collection.find(filter).await {
@@ -168,11 +776,17 @@ pub async fn get_messages_by_room_id(
 
     while let Some(result) = cursor.next().await {
         match result {
-            Ok(message) => {
+            Ok(mut message) => {
+                if let Some(poll) = message.poll.as_mut() {
+                    poll.tally();
+                }
                 messages.push(GetRoomMessages {
                     sender_id: message.sender_id,
                     room_id: message.room_id,
                     content: message.content,
+                    reply_to: message.reply_to,
+                    poll: message.poll,
+                    bot: message.bot,
                 });
             }
             Err(e) => {
@@ -220,6 +834,8 @@ pub async fn get_messages_in_dm(
                 sender_id: message.sender_id,
                 receiver_id: message.receiver_id,
                 content: message.content,
+                reply_to: message.reply_to,
+                bot: message.bot,
             }),
             Err(e) => {
                 println!("Some error occured: {e}");
@@ -235,18 +851,21 @@ pub async fn get_messages_in_dm(
 
 pub async fn delete_message_in_room(
     State(db): State<Arc<Database>>,
-    Path(id): Path<String>,
+    Path((room_id, id)): Path<(String, String)>,
     claims: Claims,
 ) -> Result<String, (StatusCode, String)> {
     let collection: Collection<Message> = db.collection("message");
     let room_collection: Collection<Room> = db.collection("room");
 
+    let room_obj_id = ObjectId::parse_str(room_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
     let message_obj_id = ObjectId::parse_str(id)
         .map_err(|_| (StatusCode::NOT_FOUND, "Wrong Room Id".to_string()))?;
 
     let user_id = claims.user_id;
 
-    let filter = doc! {"_id": message_obj_id};
+    // Only messages of the room in the path, its membership was checked on the way in
+    let filter = doc! {"_id": message_obj_id, "room_id": room_obj_id};
 
     match collection.find_one(filter.clone()).await {
         Ok(Some(message_found)) => {
@@ -257,51 +876,64 @@ pub async fn delete_message_in_room(
                         "Internal Server Error".to_string(),
                     )
                 })?;
-                return Ok("Message is deleted successfully by the sender himself".to_string());
+                if let Some(room_id) = message_found.room_id {
+                    events::emit(
+                        &db,
+                        room_id,
+                        RoomEvent::MessageDeleted {
+                            message_id: message_found.id,
+                            actor_id: user_id,
+                        },
+                    );
+                }
+                Ok("Message is deleted successfully by the sender himself".to_string())
             } else {
                 match room_collection
                     .find_one(doc! {"_id": message_found.room_id})
                     .await
                 {
                     Ok(Some(room)) => {
-                        if room.owner == user_id {
-                            collection.delete_one(filter.clone()).await.map_err(|_| {
-                                (
-                                    StatusCode::INTERNAL_SERVER_ERROR,
-                                    "Internal Server Error".to_string(),
-                                )
-                            })?;
-                            return Ok("Message Deleted Successfully by the owner".to_string());
-                        } else {
-                            return Err((
-                                StatusCode::FORBIDDEN,
-                                "You don't have permission to delete this message".to_string(),
-                            ));
-                        }
+                        policy::check(&db, &room, user_id, Permission::DeleteOthersMessages)
+                            .await?;
+                        collection.delete_one(filter.clone()).await.map_err(|_| {
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                "Internal Server Error".to_string(),
+                            )
+                        })?;
+                        events::emit(
+                            &db,
+                            room.id,
+                            RoomEvent::MessageDeleted {
+                                message_id: message_found.id,
+                                actor_id: user_id,
+                            },
+                        );
+                        Ok("Message Deleted Successfully by a moderator".to_string())
                     }
                     Ok(None) => {
-                        return Err((
+                        Err((
                             StatusCode::INTERNAL_SERVER_ERROR,
                             "Internal Server Error".to_string(),
-                        ));
+                        ))
                     }
                     Err(e) => {
                         println!("Some Error Occured: {e}");
-                        return Err((
+                        Err((
                             StatusCode::INTERNAL_SERVER_ERROR,
                             "Internal Server Error".to_string(),
-                        ));
+                        ))
                     }
                 }
             }
         }
-        Ok(None) => return Err((StatusCode::NOT_FOUND, "messagenot found".to_string())),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "messagenot found".to_string())),
         Err(e) => {
             println!("Some error occured: {e}");
-            return Err((
+            Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal Server Error".to_string(),
-            ));
+            ))
         }
     }
 }
@@ -328,65 +960,118 @@ pub async fn delete_message_in_dm(
                         "Internal Server Error".to_string(),
                     )
                 })?;
-                return Ok("the message deleted successfully".to_string());
+                Ok("the message deleted successfully".to_string())
             } else {
-                return Err((
+                Err((
                     StatusCode::FORBIDDEN,
                     "You have no right to delete the message".to_string(),
-                ));
+                ))
             }
         }
         Ok(None) => {
-            return Err((StatusCode::NOT_FOUND, "Message Not Found".to_string()));
+            Err((StatusCode::NOT_FOUND, "Message Not Found".to_string()))
         }
         Err(e) => {
             println!("Some Error Occured: {e}");
-            return Err((
+            Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal Server Error".to_string(),
-            ));
+            ))
         }
     }
 }
 
 pub async fn get_users_with_recent_chats(
     State(db): State<Arc<Database>>,
+    claims: Claims,
     Path(current_user_id): Path<String>,
 ) -> Result<Json<Vec<RecentChat>>, (StatusCode, String)> {
     let messages: Collection<mongodb::bson::Document> = db.collection("message");
 
-    let parsed_user_id = ObjectId::parse_str(&current_user_id)
-        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid user ID".to_string()))?;
+    // The id in the path is kept for the clients, the chats are always the caller's own
+    let parsed_user_id = claims.user_id;
+    if current_user_id != parsed_user_id.to_hex() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You can only list your own chats".to_string(),
+        ));
+    }
+
+    let group_collection: Collection<GroupDm> = db.collection("group_dm");
+    let groups: Vec<GroupDm> = group_collection
+        .find(doc! {"members": &parsed_user_id})
+        .await
+        .map_err(|e| {
+            eprintln!("Error fetching recent chats: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to fetch recent chats".to_string(),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            eprintln!("Error fetching recent chats: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to fetch recent chats".to_string(),
+            )
+        })?;
+    let group_ids: Vec<ObjectId> = groups.iter().map(|group| group.id).collect();
+
+    // Only the rooms the caller is in, deleted rooms lose their memberships with the room
+    let membership_collection: Collection<Membership> = db.collection("membership");
+    let room_ids = membership_collection
+        .distinct("room_id", doc! {"user_id": &parsed_user_id})
+        .await
+        .map_err(|e| {
+            eprintln!("Error fetching recent chats: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to fetch recent chats".to_string(),
+            )
+        })?;
 
     let pipeline = vec![
-        // Get messages where the current user is either sender, receiver, or in a room
+        // Get direct messages of the current user and messages in their rooms and group DMs
         doc! {
             "$match": {
                 "$or": [
-                    { "sender_id": &parsed_user_id },
-                    { "receiver_id": &parsed_user_id },
-                    { "room_id": { "$ne": null } }
+                    {
+                        "room_id": null,
+                        "group_id": null,
+                        "$or": [
+                            { "sender_id": &parsed_user_id },
+                            { "receiver_id": &parsed_user_id }
+                        ]
+                    },
+                    { "room_id": { "$in": room_ids } },
+                    { "group_id": { "$in": &group_ids } }
                 ]
             }
         },
         // Sort newest first
         doc! { "$sort": { "timestamp": -1 } },
-        // Group: direct chats by "other user", rooms by "room_id"
+        // Group: direct chats by "other user", rooms by "room_id", group DMs by "group_id"
         doc! {
             "$group": {
                 "_id": {
                     "$cond": [
                         { "$ifNull": ["$room_id", false] }, // if room_id exists
                         { "type": "room", "id": "$room_id" },
-                        { "type": "user",
-                          "id": {
-                              "$cond": [
-                                  { "$eq": ["$sender_id", &parsed_user_id] },
-                                  "$receiver_id",
-                                  "$sender_id"
-                              ]
-                          }
-                        }
+                        { "$cond": [
+                            { "$ifNull": ["$group_id", false] }, // if group_id exists
+                            { "type": "group", "id": "$group_id" },
+                            { "type": "user",
+                              "id": {
+                                  "$cond": [
+                                      { "$eq": ["$sender_id", &parsed_user_id] },
+                                      "$receiver_id",
+                                      "$sender_id"
+                                  ]
+                              }
+                            }
+                        ] }
                     ]
                 },
                 "last_message": { "$first": "$content" },
@@ -410,18 +1095,52 @@ pub async fn get_users_with_recent_chats(
                 "as": "room_info"
             }
         },
+        doc! {
+            "$lookup": {
+                "from": "bot",
+                "localField": "_id.id",
+                "foreignField": "_id",
+                "as": "bot_info"
+            }
+        },
+        doc! {
+            "$lookup": {
+                "from": "group_dm",
+                "localField": "_id.id",
+                "foreignField": "_id",
+                "as": "group_info"
+            }
+        },
         // Decide name field based on type
         doc! {
             "$addFields": {
                 "chat_type": "$_id.type",
                 "chat_id": "$_id.id",
+                // Unnamed group DMs get their member names below
                 "name": {
-                    "$cond": [
-                        { "$eq": ["$_id.type", "user"] },
-                        { "$arrayElemAt": ["$user_info.name", 0] },
-                        { "$arrayElemAt": ["$room_info.name", 0] }
-                    ]
-                }
+                    "$switch": {
+                        "branches": [
+                            {
+                                "case": { "$eq": ["$_id.type", "user"] },
+                                "then": {
+                                    "$ifNull": [
+                                        { "$arrayElemAt": ["$user_info.name", 0] },
+                                        { "$arrayElemAt": ["$bot_info.name", 0] }
+                                    ]
+                                }
+                            },
+                            {
+                                "case": { "$eq": ["$_id.type", "room"] },
+                                "then": { "$arrayElemAt": ["$room_info.name", 0] }
+                            }
+                        ],
+                        "default": { "$ifNull": [{ "$arrayElemAt": ["$group_info.name", 0] }, ""] }
+                    }
+                },
+                "archived": {
+                    "$gt": [{ "$arrayElemAt": ["$room_info.archived_at", 0] }, null]
+                },
+                "bot": { "$gt": [{ "$size": "$bot_info" }, 0] }
             }
         },
         // Cleanup
@@ -432,11 +1151,13 @@ pub async fn get_users_with_recent_chats(
                 "chat_type": 1,
                 "name": 1,
                 "last_message": 1,
-                "timestamp": 1
+                "timestamp": 1,
+                "archived": 1,
+                "bot": 1
             }
         },
-        // Sort by latest again
-        doc! { "$sort": { "timestamp": -1 } },
+        // Sort by latest again, archived rooms last
+        doc! { "$sort": { "archived": 1, "timestamp": -1 } },
     ];
 
     let mut cursor = messages.aggregate(pipeline).await.map_err(|e| {
@@ -449,10 +1170,58 @@ pub async fn get_users_with_recent_chats(
 
     let mut results = Vec::new();
     while let Some(doc) = cursor.next().await {
-        if let Ok(d) = doc {
-            if let Ok(chat) = mongodb::bson::from_document::<RecentChat>(d) {
-                results.push(chat);
-            }
+        if let Ok(d) = doc
+            && let Ok(chat) = mongodb::bson::from_document::<RecentChat>(d)
+        {
+            results.push(chat);
+        }
+    }
+
+    if results
+        .iter()
+        .any(|chat| chat.chat_type == "group" && chat.name.is_empty())
+    {
+        let user_collection: Collection<User> = db.collection("user");
+        let member_ids: Vec<ObjectId> = groups
+            .iter()
+            .flat_map(|group| group.members.iter().copied())
+            .collect();
+        let users: Vec<User> = user_collection
+            .find(doc! {"_id": { "$in": member_ids }})
+            .await
+            .map_err(|e| {
+                eprintln!("Error fetching recent chats: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to fetch recent chats".to_string(),
+                )
+            })?
+            .try_collect()
+            .await
+            .map_err(|e| {
+                eprintln!("Error fetching recent chats: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to fetch recent chats".to_string(),
+                )
+            })?;
+
+        for chat in results
+            .iter_mut()
+            .filter(|chat| chat.chat_type == "group" && chat.name.is_empty())
+        {
+            let Some(group) = groups.iter().find(|group| group.id == chat.chat_id) else {
+                continue;
+            };
+            // Everyone else in the group, by name
+            chat.name = group
+                .members
+                .iter()
+                .filter(|member| **member != parsed_user_id)
+                .filter_map(|member| users.iter().find(|user| user.id == *member))
+                .map(|user| user.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
         }
     }
 
@@ -461,30 +1230,38 @@ pub async fn get_users_with_recent_chats(
 
 pub async fn get_messages_between_users(
     State(db): State<Arc<Database>>,
+    claims: Claims,
     Path((user1_id, user2_id)): Path<(String, String)>,
 ) -> Result<Json<Vec<Message>>, StatusCode> {
+    claims
+        .require_scope("messages:read")
+        .map_err(|(status, _)| status)?;
+
     // Convert both path params to ObjectId
-    let user1_oid = ObjectId::parse_str(&user1_id)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-    let user2_oid = ObjectId::parse_str(&user2_id)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let user1_oid = ObjectId::parse_str(&user1_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let user2_oid = ObjectId::parse_str(&user2_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    // A conversation is only readable by the two people in it
+    if claims.user_id != user1_oid && claims.user_id != user2_oid {
+        return Err(StatusCode::FORBIDDEN);
+    }
 
     let collection = db.collection::<Message>("message");
 
     // MongoDB aggregation pipeline
     let pipeline = vec![
-    doc! {
-        "$match": {
-            "$or": [
-                { "$and": [ { "sender_id": user1_oid.clone() }, { "receiver_id": user2_oid.clone() } ] },
-                { "$and": [ { "sender_id": user2_oid.clone() }, { "receiver_id": user1_oid.clone() } ] }
-            ]
-        }
-    },
-    doc! {
-        "$sort": { "timestamp": 1 }
-    }
-];
+        doc! {
+            "$match": {
+                "$or": [
+                    { "$and": [ { "sender_id": user1_oid }, { "receiver_id": user2_oid } ] },
+                    { "$and": [ { "sender_id": user2_oid }, { "receiver_id": user1_oid } ] }
+                ]
+            }
+        },
+        doc! {
+            "$sort": { "timestamp": 1 }
+        },
+    ];
 
     let mut cursor = collection
         .aggregate(pipeline)
@@ -497,7 +1274,10 @@ pub async fn get_messages_between_users(
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
     {
-        if let Ok(message) = bson::from_document::<Message>(doc) {
+        if let Ok(mut message) = bson::from_document::<Message>(doc) {
+            if let Some(poll) = message.poll.as_mut() {
+                poll.tally();
+            }
             messages.push(message);
         }
     }
@@ -507,11 +1287,16 @@ pub async fn get_messages_between_users(
 
 pub async fn get_messages_in_room(
     State(db): State<Arc<Database>>,
+    claims: Claims,
     Path(room_id): Path<String>,
 ) -> Result<Json<Vec<Message>>, StatusCode> {
-    // Convert room_id string to ObjectId
-    let room_oid = ObjectId::parse_str(&room_id)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    claims
+        .require_scope("messages:read")
+        .map_err(|(status, _)| status)?;
+
+    let room = policy::find_room(&db, &room_id, claims.user_id, Permission::ReadMessages)
+        .await
+        .map_err(|(status, _)| status)?;
 
     let collection: Collection<Message> = db.collection("message");
 
@@ -519,12 +1304,12 @@ pub async fn get_messages_in_room(
     let pipeline = vec![
         doc! {
             "$match": {
-                "room_id": room_oid.clone()
+                "room_id": room.id
             }
         },
         doc! {
             "$sort": { "timestamp": 1 }
-        }
+        },
     ];
 
     let mut cursor = collection
@@ -538,10 +1323,190 @@ pub async fn get_messages_in_room(
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
     {
-        if let Ok(message) = bson::from_document::<Message>(doc) {
+        if let Ok(mut message) = bson::from_document::<Message>(doc) {
+            if let Some(poll) = message.poll.as_mut() {
+                poll.tally();
+            }
             messages.push(message);
         }
     }
 
     Ok(Json(messages))
-}
\ No newline at end of file
+}
+
+// Pinned messages stay on top of the room, for moderators and up
+pub async fn pin_message(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, message_id)): Path<(String, String)>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<Message> = db.collection("message");
+
+    let room = policy::find_room(&db, &room_id, claims.user_id, Permission::Pin).await?;
+    let message_obj_id = ObjectId::parse_str(&message_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Message Id".to_string()))?;
+
+    let pinned = PinnedBy {
+        user_id: claims.user_id,
+        pinned_at: DateTime::now(),
+    };
+    let pinned = bson::to_bson(&pinned).map_err(|e| {
+        println!("Error in serializing the pin: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal Server Error".to_string(),
+        )
+    })?;
+
+    let result = collection
+        .update_one(
+            doc! {"_id": message_obj_id, "room_id": room.id},
+            doc! {"$set": {"pinned": pinned}},
+        )
+        .await
+        .map_err(internal_error)?;
+
+    if result.matched_count == 0 {
+        return Err((StatusCode::NOT_FOUND, "Message not found".to_string()));
+    }
+
+    Ok("The message is pinned".to_string())
+}
+
+pub async fn unpin_message(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, message_id)): Path<(String, String)>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<Message> = db.collection("message");
+
+    let room = policy::find_room(&db, &room_id, claims.user_id, Permission::Pin).await?;
+    let message_obj_id = ObjectId::parse_str(&message_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Message Id".to_string()))?;
+
+    let result = collection
+        .update_one(
+            doc! {"_id": message_obj_id, "room_id": room.id},
+            doc! {"$unset": {"pinned": ""}},
+        )
+        .await
+        .map_err(internal_error)?;
+
+    if result.matched_count == 0 {
+        return Err((StatusCode::NOT_FOUND, "Message not found".to_string()));
+    }
+
+    Ok("The message is unpinned".to_string())
+}
+
+pub async fn get_pinned_messages(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<Message>>, (StatusCode, String)> {
+    let collection: Collection<Message> = db.collection("message");
+
+    let room = policy::find_room(&db, &room_id, claims.user_id, Permission::ReadMessages).await?;
+
+    let mut messages: Vec<Message> = collection
+        .find(doc! {"room_id": room.id, "pinned": {"$exists": true}})
+        .sort(doc! {"pinned.pinned_at": -1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    for message in &mut messages {
+        if let Some(poll) = message.poll.as_mut() {
+            poll.tally();
+        }
+    }
+
+    Ok(Json(messages))
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::Client;
+    use std::env;
+
+    // Refused before anything is read, so this holds without a database
+    #[tokio::test]
+    async fn direct_messages_are_only_open_to_the_two_people() {
+        let client = Client::with_uri_str("mongodb://127.0.0.1:1").await.unwrap();
+        let db = Arc::new(client.database("RustChat"));
+        let (a, b) = (ObjectId::new(), ObjectId::new());
+        let claims = Claims {
+            user_id: ObjectId::new(),
+            exp: 0,
+            iat: 0,
+            bot: None,
+        };
+
+        let status = get_messages_between_users(State(db), claims, Path((a.to_hex(), b.to_hex())))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB in TEST_DB, run with --ignored"]
+    async fn recent_chats_only_list_the_callers_rooms() {
+        let url = env::var("TEST_DB").expect("TEST_DB is not set");
+        let client = Client::with_uri_str(&url).await.unwrap();
+        let db = Arc::new(client.database(&format!("RustChatTest{}", ObjectId::new())));
+
+        let (me, other) = (ObjectId::new(), ObjectId::new());
+        let (mine, theirs) = (ObjectId::new(), ObjectId::new());
+        let now = DateTime::now();
+
+        let rooms: Collection<bson::Document> = db.collection("room");
+        rooms
+            .insert_many([
+                doc! { "_id": mine, "name": "Mine", "owner": me, "created_at": now },
+                doc! { "_id": theirs, "name": "Private", "owner": other, "visibility": "private", "created_at": now },
+            ])
+            .await
+            .unwrap();
+        db.collection::<Membership>("membership")
+            .insert_many([
+                Membership::new(mine, me, RoomRole::Owner, false),
+                Membership::new(theirs, other, RoomRole::Owner, false),
+            ])
+            .await
+            .unwrap();
+        let messages: Collection<bson::Document> = db.collection("message");
+        messages
+            .insert_many([
+                doc! { "sender_id": me, "room_id": mine, "content": "Hi", "timestamp": now },
+                doc! { "sender_id": other, "room_id": theirs, "content": "Secret", "timestamp": now },
+            ])
+            .await
+            .unwrap();
+
+        let claims = Claims {
+            user_id: me,
+            exp: 0,
+            iat: 0,
+            bot: None,
+        };
+        let chats = get_users_with_recent_chats(State(db.clone()), claims, Path(me.to_hex()))
+            .await
+            .unwrap()
+            .0;
+
+        db.as_ref().drop().await.unwrap();
+        let ids: Vec<ObjectId> = chats.iter().map(|chat| chat.chat_id).collect();
+        assert_eq!(ids, vec![mine]);
+    }
+}
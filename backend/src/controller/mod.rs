@@ -1,4 +1,16 @@
 pub mod auth_controller;
 pub mod user_controller;
 pub mod room_controller;
-pub mod message_controller;
\ No newline at end of file
+pub mod message_controller;
+pub mod poll_controller;
+pub mod webhook_controller;
+pub mod bot_controller;
+pub mod command_controller;
+pub mod invite_controller;
+pub mod join_request_controller;
+pub mod moderation_controller;
+pub mod room_settings_controller;
+pub mod directory_controller;
+pub mod member_controller;
+pub mod workspace_controller;
+pub mod group_dm_controller;
@@ -0,0 +1,442 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use bson::{doc, oid::ObjectId};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::Deserialize;
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    controller::member_controller::remove_member,
+    events::{self, RoomEvent},
+    middleware::auth_middleware::Claims,
+    models::{
+        moderation_model::{ModerationAction, ModerationRecord, RestrictionKind, RoomRestriction},
+        room_model::Room,
+    },
+    policy::{self, Permission},
+    utils::db::is_duplicate_key_error,
+};
+
+const MAX_REASON_LEN: usize = 500;
+// Most recent actions returned by the moderation log
+const MODERATION_LOG_LIMIT: i64 = 100;
+
+// DTOs
+#[derive(Deserialize, Default)]
+pub struct ModerationRequest {
+    reason: Option<String>,
+    // Seconds until the ban or mute is lifted, bans are permanent when missing
+    duration: Option<u64>,
+}
+
+pub async fn kick_user(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, user_id)): Path<(String, String)>,
+    payload: Option<Json<ModerationRequest>>,
+) -> Result<String, (StatusCode, String)> {
+    let payload = moderation_request(payload)?;
+    let (room, user_obj_id) =
+        find_target(&db, &room_id, &user_id, claims.user_id, Permission::Kick).await?;
+
+    if !policy::is_member(&db, room.id, user_obj_id).await? {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The user is not part of the room".to_string(),
+        ));
+    }
+
+    kick(&db, &room, user_obj_id, claims.user_id, payload.reason).await?;
+
+    Ok("The user was removed from the room".to_string())
+}
+
+pub async fn ban_user(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, user_id)): Path<(String, String)>,
+    payload: Option<Json<ModerationRequest>>,
+) -> Result<String, (StatusCode, String)> {
+    let payload = moderation_request(payload)?;
+    let (room, user_obj_id) =
+        find_target(&db, &room_id, &user_id, claims.user_id, Permission::Ban).await?;
+
+    let expires_at = payload.duration.map(expires_in);
+    restrict(
+        &db,
+        &room,
+        user_obj_id,
+        claims.user_id,
+        RestrictionKind::Ban,
+        &payload.reason,
+        expires_at,
+    )
+    .await?;
+
+    remove_from_room(&db, &room, user_obj_id, claims.user_id).await?;
+
+    record(
+        &db,
+        room.id,
+        user_obj_id,
+        claims.user_id,
+        ModerationAction::Ban,
+        payload.reason,
+        expires_at,
+    )
+    .await?;
+
+    Ok("The user is banned from the room".to_string())
+}
+
+pub async fn unban_user(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, user_id)): Path<(String, String)>,
+    payload: Option<Json<ModerationRequest>>,
+) -> Result<String, (StatusCode, String)> {
+    let payload = moderation_request(payload)?;
+    let (room, user_obj_id) =
+        find_target(&db, &room_id, &user_id, claims.user_id, Permission::Ban).await?;
+
+    lift(&db, room.id, user_obj_id, RestrictionKind::Ban).await?;
+    record(
+        &db,
+        room.id,
+        user_obj_id,
+        claims.user_id,
+        ModerationAction::Unban,
+        payload.reason,
+        None,
+    )
+    .await?;
+
+    Ok("The ban is lifted".to_string())
+}
+
+pub async fn mute_user(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, user_id)): Path<(String, String)>,
+    payload: Option<Json<ModerationRequest>>,
+) -> Result<String, (StatusCode, String)> {
+    let payload = moderation_request(payload)?;
+    let (room, user_obj_id) =
+        find_target(&db, &room_id, &user_id, claims.user_id, Permission::Mute).await?;
+
+    if !policy::is_member(&db, room.id, user_obj_id).await? {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The user is not part of the room".to_string(),
+        ));
+    }
+
+    let expires_at = payload.duration.map(expires_in).ok_or((
+        StatusCode::BAD_REQUEST,
+        "A mute needs a duration".to_string(),
+    ))?;
+
+    restrict(
+        &db,
+        &room,
+        user_obj_id,
+        claims.user_id,
+        RestrictionKind::Mute,
+        &payload.reason,
+        Some(expires_at),
+    )
+    .await?;
+    record(
+        &db,
+        room.id,
+        user_obj_id,
+        claims.user_id,
+        ModerationAction::Mute,
+        payload.reason,
+        Some(expires_at),
+    )
+    .await?;
+
+    Ok("The user is muted".to_string())
+}
+
+pub async fn unmute_user(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, user_id)): Path<(String, String)>,
+    payload: Option<Json<ModerationRequest>>,
+) -> Result<String, (StatusCode, String)> {
+    let payload = moderation_request(payload)?;
+    let (room, user_obj_id) =
+        find_target(&db, &room_id, &user_id, claims.user_id, Permission::Mute).await?;
+
+    lift(&db, room.id, user_obj_id, RestrictionKind::Mute).await?;
+    record(
+        &db,
+        room.id,
+        user_obj_id,
+        claims.user_id,
+        ModerationAction::Unmute,
+        payload.reason,
+        None,
+    )
+    .await?;
+
+    Ok("The user is no longer muted".to_string())
+}
+
+pub async fn get_room_bans(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<RoomRestriction>>, (StatusCode, String)> {
+    let room = policy::find_room(&db, &room_id, claims.user_id, Permission::Kick).await?;
+    Ok(Json(
+        active_restrictions(&db, room.id, RestrictionKind::Ban).await?,
+    ))
+}
+
+pub async fn get_room_mutes(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<RoomRestriction>>, (StatusCode, String)> {
+    let room = policy::find_room(&db, &room_id, claims.user_id, Permission::Kick).await?;
+    Ok(Json(
+        active_restrictions(&db, room.id, RestrictionKind::Mute).await?,
+    ))
+}
+
+pub async fn get_moderation_log(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<ModerationRecord>>, (StatusCode, String)> {
+    let collection: Collection<ModerationRecord> = db.collection("moderation_log");
+
+    let room = policy::find_room(&db, &room_id, claims.user_id, Permission::Kick).await?;
+
+    let records: Vec<ModerationRecord> = collection
+        .find(doc! {"room_id": room.id})
+        .sort(doc! {"created_at": -1})
+        .limit(MODERATION_LOG_LIMIT)
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(records))
+}
+
+// Removes a member and records who did it, also used by the /kick command
+pub async fn kick(
+    db: &Arc<Database>,
+    room: &Room,
+    user_id: ObjectId,
+    actor_id: ObjectId,
+    reason: Option<String>,
+) -> Result<(), (StatusCode, String)> {
+    remove_from_room(db, room, user_id, actor_id).await?;
+    record(
+        db,
+        room.id,
+        user_id,
+        actor_id,
+        ModerationAction::Kick,
+        reason,
+        None,
+    )
+    .await
+}
+
+// Banned users may not be members anymore, then there is nothing to remove
+async fn remove_from_room(
+    db: &Arc<Database>,
+    room: &Room,
+    user_id: ObjectId,
+    actor_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+    if remove_member(db, room.id, user_id).await? {
+        events::emit(db, room.id, RoomEvent::MemberLeft { user_id, actor_id });
+    }
+
+    Ok(())
+}
+
+// Replaces any earlier ban or mute of the same kind
+async fn restrict(
+    db: &Database,
+    room: &Room,
+    user_id: ObjectId,
+    actor_id: ObjectId,
+    kind: RestrictionKind,
+    reason: &Option<String>,
+    expires_at: Option<DateTime>,
+) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<RoomRestriction> = db.collection("room_restriction");
+
+    lift(db, room.id, user_id, kind).await?;
+
+    let restriction = RoomRestriction {
+        id: ObjectId::new(),
+        room_id: room.id,
+        user_id,
+        kind,
+        actor_id,
+        reason: reason.clone(),
+        created_at: DateTime::now(),
+        expires_at,
+    };
+
+    match collection.insert_one(&restriction).await {
+        Ok(_) => Ok(()),
+        Err(e) if is_duplicate_key_error(&e) => Err((
+            StatusCode::CONFLICT,
+            "Someone else changed this at the same time, try again".to_string(),
+        )),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+async fn lift(
+    db: &Database,
+    room_id: ObjectId,
+    user_id: ObjectId,
+    kind: RestrictionKind,
+) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<RoomRestriction> = db.collection("room_restriction");
+
+    collection
+        .delete_one(doc! {"room_id": room_id, "user_id": user_id, "kind": kind.as_str()})
+        .await
+        .map_err(internal_error)?;
+
+    Ok(())
+}
+
+async fn record(
+    db: &Database,
+    room_id: ObjectId,
+    user_id: ObjectId,
+    actor_id: ObjectId,
+    action: ModerationAction,
+    reason: Option<String>,
+    expires_at: Option<DateTime>,
+) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<ModerationRecord> = db.collection("moderation_log");
+
+    let record = ModerationRecord {
+        id: ObjectId::new(),
+        room_id,
+        user_id,
+        actor_id,
+        action,
+        reason,
+        expires_at,
+        created_at: DateTime::now(),
+    };
+
+    collection
+        .insert_one(&record)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(())
+}
+
+async fn active_restrictions(
+    db: &Database,
+    room_id: ObjectId,
+    kind: RestrictionKind,
+) -> Result<Vec<RoomRestriction>, (StatusCode, String)> {
+    let collection: Collection<RoomRestriction> = db.collection("room_restriction");
+
+    collection
+        .find(doc! {
+            "room_id": room_id,
+            "kind": kind.as_str(),
+            "$or": [ { "expires_at": null }, { "expires_at": { "$gt": DateTime::now() } } ]
+        })
+        .sort(doc! {"created_at": -1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)
+}
+
+// The caller needs the permission and must outrank the target
+async fn find_target(
+    db: &Database,
+    room_id: &str,
+    user_id: &str,
+    actor_id: ObjectId,
+    permission: Permission,
+) -> Result<(Room, ObjectId), (StatusCode, String)> {
+    let user_obj_id = ObjectId::parse_str(user_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid User Id".to_string()))?;
+
+    let room = policy::find_room(db, room_id, actor_id, permission).await?;
+    policy::check_not_archived(&room)?;
+
+    if user_obj_id == actor_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You can't do this to yourself".to_string(),
+        ));
+    }
+
+    policy::check_outranks(db, &room, actor_id, user_obj_id, permission).await?;
+
+    Ok((room, user_obj_id))
+}
+
+fn moderation_request(
+    payload: Option<Json<ModerationRequest>>,
+) -> Result<ModerationRequest, (StatusCode, String)> {
+    let mut payload = payload.map(|Json(request)| request).unwrap_or_default();
+
+    payload.reason = payload
+        .reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+
+    if payload
+        .reason
+        .as_ref()
+        .is_some_and(|reason| reason.chars().count() > MAX_REASON_LEN)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The reason can be at most {MAX_REASON_LEN} characters"),
+        ));
+    }
+
+    if payload.duration == Some(0) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The duration must be above zero".to_string(),
+        ));
+    }
+
+    Ok(payload)
+}
+
+fn expires_in(secs: u64) -> DateTime {
+    let millis = i64::try_from(secs).unwrap_or(i64::MAX).saturating_mul(1000);
+    DateTime::from_millis(DateTime::now().timestamp_millis().saturating_add(millis))
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
@@ -0,0 +1,382 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    controller::message_controller::{PostError, take_slow_mode},
+    events::{self, RoomEvent},
+    middleware::auth_middleware::Claims,
+    models::{
+        message_model::{BotSender, Message},
+        moderation_model::RestrictionKind,
+        poll_model::{Poll, PollOption},
+        room_model::{Room, RoomRole},
+    },
+    policy::{self, Permission},
+};
+
+const MAX_POLL_OPTIONS: usize = 10;
+
+// DTOs
+#[derive(Deserialize)]
+pub struct PollRequest {
+    question: String,
+    options: Vec<String>,
+    #[serde(default)]
+    multiple_choice: bool,
+    #[serde(default)]
+    anonymous: bool,
+    deadline: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct VoteRequest {
+    // Indexes into the poll options
+    options: Vec<usize>,
+}
+
+#[derive(Serialize)]
+pub struct PollResponse {
+    msg: String,
+    message_id: ObjectId,
+    poll: Poll,
+}
+
+pub async fn create_poll(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+    Json(payload): Json<PollRequest>,
+) -> Result<Json<PollResponse>, PostError> {
+    let message_collection: Collection<Message> = db.collection("message");
+
+    claims.require_scope("messages:write")?;
+
+    let room_obj_id = ObjectId::parse_str(&room_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
+
+    let question = payload.question.trim().to_string();
+    let options: Vec<String> = payload
+        .options
+        .iter()
+        .map(|option| option.trim().to_string())
+        .collect();
+
+    if question.is_empty() || options.iter().any(|option| option.is_empty()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The question and options can't be empty".to_string(),
+        )
+            .into());
+    }
+
+    if options.len() < 2 || options.len() > MAX_POLL_OPTIONS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("A poll needs between 2 and {MAX_POLL_OPTIONS} options"),
+        )
+            .into());
+    }
+
+    let deadline = match payload.deadline {
+        Some(deadline) if deadline <= Utc::now() => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "The deadline must be in the future".to_string(),
+            )
+                .into());
+        }
+        Some(deadline) => Some(DateTime::from_millis(deadline.timestamp_millis())),
+        None => None,
+    };
+
+    let (room, role) = find_room_to_post(&db, room_obj_id, claims.user_id).await?;
+    policy::check_not_restricted(&db, room_obj_id, claims.user_id, RestrictionKind::Mute).await?;
+    let slot = take_slow_mode(&db, &room, claims.user_id, role).await?;
+
+    let poll = Poll {
+        question: question.clone(),
+        options: options
+            .into_iter()
+            .map(|text| PollOption {
+                text,
+                voters: Vec::new(),
+                votes: 0,
+            })
+            .collect(),
+        multiple_choice: payload.multiple_choice,
+        anonymous: payload.anonymous,
+        deadline,
+        closed: false,
+    };
+
+    let new_message = Message {
+        id: ObjectId::new(),
+        sender_id: claims.user_id,
+        receiver_id: None,
+        room_id: Some(room_obj_id),
+        group_id: None,
+        content: question,
+        timestamp: DateTime::now(),
+        forwarded_from: None,
+        reply_to: None,
+        poll: Some(poll.clone()),
+        bot: claims.bot.as_ref().map(|bot| BotSender {
+            bot_id: claims.user_id,
+            name: bot.name.clone(),
+        }),
+        pinned: None,
+    };
+
+    match message_collection.insert_one(&new_message).await {
+        Ok(_) => {
+            events::emit(
+                &db,
+                room_obj_id,
+                RoomEvent::MessageCreated(Box::new(new_message.clone())),
+            );
+            Ok(Json(PollResponse {
+                msg: "The poll was created successfully".to_string(),
+                message_id: new_message.id,
+                poll,
+            }))
+        }
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            if let Some(slot) = &slot {
+                slot.release(&db).await;
+            }
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+                .into())
+        }
+    }
+}
+
+pub async fn vote_poll(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, id)): Path<(String, String)>,
+    Json(payload): Json<VoteRequest>,
+) -> Result<Json<PollResponse>, (StatusCode, String)> {
+    let message_collection: Collection<Message> = db.collection("message");
+    let user_obj_id = claims.user_id;
+
+    claims.require_scope("messages:write")?;
+
+    let room_id = ObjectId::parse_str(&room_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
+    let (message, poll) = find_poll(&db, room_id, &id).await?;
+
+    // Step 1: Only members who can post in the room can vote
+    find_room_to_post(&db, room_id, user_obj_id).await?;
+
+    // Step 2: Validate the choice
+    if !poll.is_open() {
+        return Err((StatusCode::BAD_REQUEST, "The poll is closed".to_string()));
+    }
+
+    let mut choices = payload.options.clone();
+    choices.sort_unstable();
+    choices.dedup();
+
+    if choices.is_empty() || choices.iter().any(|index| *index >= poll.options.len()) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid poll option".to_string()));
+    }
+
+    if !poll.multiple_choice && choices.len() > 1 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "This poll only allows a single choice".to_string(),
+        ));
+    }
+
+    // Step 3: Replace any earlier vote by the user in a single update, checked against
+    // the poll still being open when it is applied
+    let choices: Vec<i64> = choices.iter().map(|index| *index as i64).collect();
+    let now = DateTime::now();
+    let open_poll = doc! {
+        "_id": message.id,
+        "poll.closed": false,
+        "$or": [ { "poll.deadline": null }, { "poll.deadline": { "$gt": now } } ]
+    };
+    let replace_vote = vec![doc! {
+        "$set": {
+            "poll.options": {
+                "$map": {
+                    "input": { "$range": [0, { "$size": "$poll.options" }] },
+                    "as": "index",
+                    "in": {
+                        "$let": {
+                            "vars": { "option": { "$arrayElemAt": ["$poll.options", "$$index"] } },
+                            "in": {
+                                "$mergeObjects": ["$$option", {
+                                    "voters": {
+                                        "$concatArrays": [
+                                            {
+                                                "$filter": {
+                                                    "input": "$$option.voters",
+                                                    "cond": { "$ne": ["$$this", user_obj_id] }
+                                                }
+                                            },
+                                            {
+                                                "$cond": [
+                                                    { "$in": ["$$index", &choices] },
+                                                    [user_obj_id],
+                                                    []
+                                                ]
+                                            }
+                                        ]
+                                    }
+                                }]
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }];
+
+    let result = message_collection
+        .update_one(open_poll, replace_vote)
+        .await
+        .map_err(|e| {
+            println!("Some error occurred: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?;
+
+    // Closed or past its deadline since it was read
+    if result.matched_count == 0 {
+        return Err((StatusCode::BAD_REQUEST, "The poll is closed".to_string()));
+    }
+
+    let (message, mut poll) = find_poll(&db, room_id, &id).await?;
+    poll.tally();
+
+    Ok(Json(PollResponse {
+        msg: "Your vote was recorded".to_string(),
+        message_id: message.id,
+        poll,
+    }))
+}
+
+pub async fn close_poll(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, id)): Path<(String, String)>,
+) -> Result<Json<PollResponse>, (StatusCode, String)> {
+    let message_collection: Collection<Message> = db.collection("message");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let room_id = ObjectId::parse_str(&room_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
+    let (message, _) = find_poll(&db, room_id, &id).await?;
+
+    // The creator of the poll or a moderator of the room can close it early
+    if message.sender_id != claims.user_id {
+        let room = room_collection
+            .find_one(doc! {"_id": room_id})
+            .await
+            .map_err(|e| {
+                println!("Some error occurred: {e}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error".to_string(),
+                )
+            })?
+            .ok_or((StatusCode::NOT_FOUND, "Room not found".to_string()))?;
+
+        policy::check(&db, &room, claims.user_id, Permission::DeleteOthersMessages).await?;
+    }
+
+    message_collection
+        .update_one(
+            doc! {"_id": message.id},
+            doc! { "$set": { "poll.closed": true } },
+        )
+        .await
+        .map_err(|e| {
+            println!("Some error occurred: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?;
+
+    let (message, mut poll) = find_poll(&db, room_id, &id).await?;
+    poll.tally();
+
+    Ok(Json(PollResponse {
+        msg: "The poll is closed".to_string(),
+        message_id: message.id,
+        poll,
+    }))
+}
+
+// Polls are looked up within the room in the path, whose membership was checked on the way in
+async fn find_poll(
+    db: &Database,
+    room_id: ObjectId,
+    id: &str,
+) -> Result<(Message, Poll), (StatusCode, String)> {
+    let message_collection: Collection<Message> = db.collection("message");
+
+    let message_obj_id = ObjectId::parse_str(id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Message Id".to_string()))?;
+
+    match message_collection
+        .find_one(doc! {"_id": message_obj_id, "room_id": room_id})
+        .await
+    {
+        Ok(Some(mut message)) => match message.poll.take() {
+            Some(poll) => Ok((message, poll)),
+            None => Err((StatusCode::BAD_REQUEST, "The message is not a poll".to_string())),
+        },
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Message Not Found".to_string())),
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ))
+        }
+    }
+}
+
+// Creating polls and voting both count as posting in the room
+async fn find_room_to_post(
+    db: &Database,
+    room_id: ObjectId,
+    user_id: ObjectId,
+) -> Result<(Room, RoomRole), (StatusCode, String)> {
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let room = match room_collection.find_one(doc! {"_id": room_id}).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "Room not found".to_string())),
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ));
+        }
+    };
+
+    let role = policy::check(db, &room, user_id, Permission::PostMessages).await?;
+
+    Ok((room, role))
+}
@@ -3,21 +3,55 @@ use axum::{
     extract::{Path, State},
     http::StatusCode,
 };
-use bson::{doc, oid::ObjectId};
-use futures_util::StreamExt;
-use mongodb::{Collection, Database};
+use bson::{Bson, Document, doc, oid::ObjectId};
+use futures_util::{FutureExt, TryStreamExt};
+use mongodb::{Collection, Database, bson::DateTime};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 //crates
+use crate::controller::directory_controller::visible_rooms;
+use crate::controller::join_request_controller::create_join_request;
+use crate::controller::member_controller::{add_member, remove_member, successor};
+use crate::controller::workspace_controller::{workspace_role, workspaces_of};
+use crate::events::{self, RoomEvent};
 use crate::middleware::auth_middleware::Claims;
-use crate::models::room_model::Room;
+use crate::models::membership_model::Membership;
+use crate::models::nonce_model::MessageNonce;
+use crate::models::room_model::{DeletedRoom, Room, RoomRole, RoomVisibility};
 use crate::models::user_model::User;
+use crate::models::webhook_model::{OutgoingWebhook, WebhookDelivery};
+use crate::models::workspace_model::Workspace;
+use crate::policy::{self, Permission};
+
+// Messages of a deleted room removed per round trip
+const PURGE_BATCH_SIZE: i64 = 1000;
+const PURGE_MAX_ATTEMPTS: u32 = 5;
+const PURGE_FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+// Rooms whose messages are still there are looked at again this often
+const PURGE_SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
 // DTOs
 #[derive(Deserialize)]
 pub struct RoomRequest {
     name: String,
+    #[serde(default)]
+    visibility: RoomVisibility,
+    // Left out for a room open to the whole server
+    workspace_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RoleRequest {
+    role: RoomRole,
+}
+
+#[derive(Deserialize)]
+pub struct TransferRequest {
+    user_id: String,
+    // When set the target has to accept before they become the owner
+    #[serde(default)]
+    require_acceptance: bool,
 }
 
 #[derive(Serialize)]
@@ -28,9 +62,62 @@ pub struct RoomResponse {
 
 #[derive(Serialize)]
 pub struct Rooms {
+    #[serde(rename = "_id")]
+    id: ObjectId,
     name: String,
     owner: ObjectId,
-    participants: Vec<ObjectId>,
+    member_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending_owner: Option<ObjectId>,
+    visibility: RoomVisibility,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    workspace_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    created_at: DateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated_at: Option<DateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slow_mode_secs: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archived_at: Option<DateTime>,
+}
+
+// A room the caller was in that has since been deleted
+#[derive(Serialize)]
+pub struct DeletedRooms {
+    room_id: ObjectId,
+    name: String,
+    deleted_by: ObjectId,
+    deleted_at: DateTime,
+}
+
+impl From<Room> for Rooms {
+    fn from(room: Room) -> Self {
+        Rooms {
+            id: room.id,
+            created_at: room.creation_time(),
+            name: room.name,
+            owner: room.owner,
+            member_count: room.member_count,
+            topic: room.topic,
+            pending_owner: room.pending_owner,
+            visibility: room.visibility,
+            workspace_id: room.workspace_id,
+            description: room.description,
+            avatar: room.avatar,
+            tags: room.tags,
+            updated_at: room.updated_at,
+            slow_mode_secs: room.slow_mode_secs,
+            archived_at: room.archived_at,
+        }
+    }
 }
 
 pub async fn create_room(
@@ -71,65 +158,78 @@ pub async fn create_room(
         }
     };
 
+    // Rooms can only be put in a workspace the owner belongs to
+    let workspace_id = match payload.workspace_id {
+        Some(workspace_id) => {
+            let workspace_obj_id = ObjectId::parse_str(&workspace_id).map_err(|_| {
+                (StatusCode::BAD_REQUEST, "Invalid Workspace Id".to_string())
+            })?;
+            if workspace_role(&db, workspace_obj_id, owner.id)
+                .await?
+                .is_none()
+            {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    "You are not part of the workspace".to_string(),
+                ));
+            }
+            Some(workspace_obj_id)
+        }
+        None => None,
+    };
+
     let new_room = Room {
         id: ObjectId::new(),
         name: payload.name,
         owner: owner.id,
-        participants: vec![owner.id],
+        member_count: 0,
+        workspace_id,
+        topic: None,
+        invited_bots: Vec::new(),
+        pending_owner: None,
+        visibility: payload.visibility,
+        description: None,
+        avatar: None,
+        tags: Vec::new(),
+        created_at: Some(DateTime::now()),
+        updated_at: None,
+        last_activity_at: None,
+        slow_mode_secs: None,
+        archived_at: None,
+        archived_by: None,
     };
 
     match room_collection.insert_one(&new_room).await {
         Ok(result) => {
-            return Ok(Json(RoomResponse {
+            add_member(&db, new_room.id, owner.id, RoomRole::Owner, false).await?;
+            Ok(Json(RoomResponse {
                 msg: format!(
                     "The room created successfully with the name {}",
                     new_room.name
                 ),
                 room_id: result.inserted_id.as_object_id().unwrap(),
-            }));
+            }))
         }
         Err(e) => {
             println!("Error in Creating a new room: {}", e);
-            return Err((
+            Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal SErver Error".to_string(),
-            ));
+            ))
         }
     }
 }
 
 pub async fn get_room(
     State(db): State<Arc<Database>>,
+    claims: Claims,
     Path(id): Path<String>,
 ) -> Result<Json<Rooms>, (StatusCode, String)> {
-    let collection: Collection<Room> = db.collection("room");
+    claims.require_scope("messages:read")?;
 
-    if id.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "Id is not given".to_string()));
-    }
-
-    let obj_id = ObjectId::parse_str(id)
-        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid ID format".to_string()))?;
+    let room = policy::find_room(&db, &id, claims.user_id, Permission::ReadMessages).await?;
 
-    let filter = doc! {
-        "_id": obj_id
-    };
-
-    match collection.find_one(filter).await {
-        Ok(Some(room_found)) => Ok(Json(Rooms {
-            name: room_found.name,
-            owner: room_found.owner,
-            participants: room_found.participants,
-        })),
-        Ok(None) => return Err((StatusCode::NOT_FOUND, "Room not found".to_string())),
-        Err(e) => {
-            println!("Error in finding the room: {}", e);
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Database Error".to_string(),
-            ));
-        }
-    }
+    Ok(Json(Rooms::from(room)))
 }
 
 pub async fn join_room(
@@ -141,6 +241,8 @@ pub async fn join_room(
     let user_collection: Collection<User> = db.collection("user");
     let room_collection: Collection<Room> = db.collection("room");
 
+    claims.require_scope("rooms:join")?;
+
     if room_id.is_empty() {
         return Err((StatusCode::BAD_REQUEST, "Fields are empty".to_string()));
     }
@@ -156,11 +258,13 @@ pub async fn join_room(
         "_id": &room_obj_id
     };
 
-    if user_collection
-        .find_one(user_filter)
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB Error".to_string()))?
-        .is_none()
+    // Bots are authenticated by their token and have no user record
+    if claims.bot.is_none()
+        && user_collection
+            .find_one(user_filter)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "DB Error".to_string()))?
+            .is_none()
     {
         return Err((StatusCode::NOT_FOUND, "User not found".to_string()));
     };
@@ -174,77 +278,91 @@ pub async fn join_room(
         }
     };
 
-    if room.participants.contains(&user_obj_id) {
+    policy::check_can_join(&db, &room, user_obj_id, claims.bot.is_some()).await?;
+
+    if claims.bot.is_none() {
+        match room.visibility {
+            RoomVisibility::Public => {}
+            RoomVisibility::Restricted => {
+                create_join_request(&db, &room, user_obj_id).await?;
+                return Ok(Json(RoomResponse {
+                    msg: "Your request to join was sent to the room admins".to_string(),
+                    room_id: room_obj_id,
+                }));
+            }
+            RoomVisibility::Private | RoomVisibility::Hidden => {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    "This room can only be joined with an invite code".to_string(),
+                ));
+            }
+        }
+    }
+
+    if !add_member(
+        &db,
+        room.id,
+        user_obj_id,
+        RoomRole::Member,
+        claims.bot.is_some(),
+    )
+    .await?
+    {
         return Err((
             StatusCode::BAD_REQUEST,
             "User is already in the group".to_string(),
         ));
     }
 
-    let room_update_filter = doc! {
-        "$push": {
-            "participants": &user_obj_id
-        }
-    };
-
-    match room_collection
-        .find_one_and_update(room_filter, room_update_filter)
-        .await
-    {
-        Ok(_) => {
-            return Ok(Json(RoomResponse {
-                msg: format!("The user with id {}, has joined the room", user_obj_id),
-                room_id: room_obj_id,
-            }));
-        }
-        Err(e) => {
-            println!("Some error occured: {}", e);
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal Server Error".to_string(),
-            ));
-        }
+    if claims.bot.is_some() {
+        room_collection
+            .update_one(
+                room_filter,
+                doc! { "$pull": { "invited_bots": &user_obj_id } },
+            )
+            .await
+            .map_err(internal_error)?;
     }
+
+    events::emit(
+        &db,
+        room_obj_id,
+        RoomEvent::MemberJoined {
+            user_id: user_obj_id,
+            actor_id: user_obj_id,
+        },
+    );
+
+    Ok(Json(RoomResponse {
+        msg: format!("The user with id {}, has joined the room", user_obj_id),
+        room_id: room_obj_id,
+    }))
 }
 
+// Deprecated, the directory lists the same rooms a page at a time
 pub async fn get_all_rooms(
     State(db): State<Arc<Database>>,
+    claims: Claims,
 ) -> Result<Json<Vec<Rooms>>, (StatusCode, String)> {
     let collection: Collection<Room> = db.collection("room");
+    let membership_collection: Collection<Membership> = db.collection("membership");
 
-    let mut cursor = match collection.find(doc! {}).await {
-        Ok(cursor) => cursor,
-        Err(e) => {
-            println!("There is some error in finding the rooms: {}", e);
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal Server Error".to_string(),
-            ));
-        }
-    };
-
-    let mut rooms = Vec::new();
+    let my_rooms = membership_collection
+        .distinct("room_id", doc! {"user_id": claims.user_id})
+        .await
+        .map_err(internal_error)?;
+    let my_workspaces = workspaces_of(&db, claims.user_id).await?;
 
-    while let Some(result) = cursor.next().await {
-        match result {
-            Ok(room) => {
-                rooms.push(Rooms {
-                    name: room.name,
-                    owner: room.owner,
-                    participants: room.participants,
-                });
-            }
-            Err(e) => {
-                println!("Some error: {}", e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Internale Server Error".to_string(),
-                ));
-            }
-        }
-    }
+    let rooms: Vec<Room> = collection
+        .find(visible_rooms(&my_rooms, &my_workspaces))
+        .sort(doc! {"name": 1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
 
-    Ok(Json(rooms))
+    Ok(Json(rooms.into_iter().map(Rooms::from).collect()))
 }
 
 pub async fn leave_room(
@@ -254,6 +372,8 @@ pub async fn leave_room(
 ) -> Result<String, (StatusCode, String)> {
     let collection: Collection<Room> = db.collection("room");
 
+    claims.require_scope("rooms:join")?;
+
     let room_obj_id = ObjectId::parse_str(&id)
         .map_err(|_| (StatusCode::NOT_FOUND, "Wrong Room Id".to_string()))?;
 
@@ -269,35 +389,25 @@ pub async fn leave_room(
         })?
         .ok_or((StatusCode::NOT_FOUND, "Invalid Room Id".to_string()))?;
 
-    // Step 2: Check ownership
-    if room.owner == claims.user_id {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "You are the owner of the room, you can't leave".to_string(),
-        ));
-    }
+    // Step 2: Check the user can leave
+    policy::check_can_leave(&db, &room, claims.user_id).await?;
 
-    // Step 3: Check membership
-    if !room.participants.contains(&claims.user_id) {
+    // Step 3: Remove user
+    if !remove_member(&db, room.id, claims.user_id).await? {
         return Err((
             StatusCode::NOT_FOUND,
             "The user was never a part of the room".to_string(),
         ));
     }
 
-    // Step 4: Remove user
-    collection
-        .update_one(
-            doc! {"_id": &room_obj_id},
-            doc! { "$pull": { "participants": &claims.user_id } },
-        )
-        .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal Server Error".to_string(),
-            )
-        })?;
+    events::emit(
+        &db,
+        room_obj_id,
+        RoomEvent::MemberLeft {
+            user_id: claims.user_id,
+            actor_id: claims.user_id,
+        },
+    );
 
     Ok("The user has successfully left the room".to_string())
 }
@@ -324,23 +434,644 @@ pub async fn delete_room(
         }
     };
 
-    if room.owner != claims.user_id {
+    policy::check(&db, &room, claims.user_id, Permission::DeleteRoom).await?;
+
+    purge_room(&db, &room, claims.user_id).await?;
+
+    Ok("The room is deleted successfully by its owner".to_string())
+}
+
+// The rooms deleted while the caller was in them, newest first, until they dismiss them
+pub async fn get_deleted_rooms(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+) -> Result<Json<Vec<DeletedRooms>>, (StatusCode, String)> {
+    let collection: Collection<DeletedRoom> = db.collection("deleted_room");
+
+    let rooms: Vec<DeletedRoom> = collection
+        .find(doc! {"members": claims.user_id})
+        .sort(doc! {"deleted_at": -1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(
+        rooms
+            .into_iter()
+            .map(|room| DeletedRooms {
+                room_id: room.id,
+                name: room.name,
+                deleted_by: room.deleted_by,
+                deleted_at: room.deleted_at,
+            })
+            .collect(),
+    ))
+}
+
+pub async fn dismiss_deleted_room(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<DeletedRoom> = db.collection("deleted_room");
+
+    let room_obj_id = ObjectId::parse_str(id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
+
+    let result = collection
+        .update_one(
+            doc! {"_id": room_obj_id, "members": claims.user_id},
+            doc! { "$pull": { "members": claims.user_id } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    if result.matched_count == 0 {
+        return Err((StatusCode::NOT_FOUND, "Room not found".to_string()));
+    }
+
+    Ok("The deleted room was dismissed".to_string())
+}
+
+// Owners and admins change the role of members below them, ownership can't be given here
+pub async fn set_member_role(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((room_id, user_id)): Path<(String, String)>,
+    Json(payload): Json<RoleRequest>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<Membership> = db.collection("membership");
+
+    let user_obj_id = ObjectId::parse_str(&user_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid User Id".to_string()))?;
+
+    let room = policy::find_room(&db, &room_id, claims.user_id, Permission::ManageRoles).await?;
+    policy::check_not_archived(&room)?;
+
+    if policy::role_of(&db, &room, user_obj_id).await?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "The user is not part of the room".to_string(),
+        ));
+    }
+
+    let actor_role = policy::check_outranks(
+        &db,
+        &room,
+        claims.user_id,
+        user_obj_id,
+        Permission::ManageRoles,
+    )
+    .await?;
+
+    if payload.role >= actor_role {
         return Err((
-            StatusCode::UNAUTHORIZED,
-            "You have no right to delete the room".to_string(),
+            StatusCode::FORBIDDEN,
+            "You can only give roles below your own".to_string(),
         ));
     }
 
-    collections
-        .delete_one(doc! {"_id": room_obj_id})
+    collection
+        .update_one(
+            doc! {"room_id": room.id, "user_id": user_obj_id},
+            doc! { "$set": { "role": payload.role.as_str() } },
+        )
         .await
-        .map_err(|_| {
+        .map_err(internal_error)?;
+
+    Ok("The role was updated".to_string())
+}
+
+pub async fn transfer_ownership(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+    Json(payload): Json<TransferRequest>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<Room> = db.collection("room");
+    let user_collection: Collection<User> = db.collection("user");
+
+    let target_obj_id = ObjectId::parse_str(&payload.user_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid User Id".to_string()))?;
+
+    let room =
+        policy::find_room(&db, &room_id, claims.user_id, Permission::TransferOwnership).await?;
+
+    if target_obj_id == room.owner {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You already own this room".to_string(),
+        ));
+    }
+
+    if !policy::is_member(&db, room.id, target_obj_id).await? {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The room can only be handed to one of its members".to_string(),
+        ));
+    }
+
+    // Bots are members too, but they can't own a room
+    let target = user_collection
+        .find_one(doc! {"_id": target_obj_id})
+        .await
+        .map_err(|e| {
+            println!("Some error occurred: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "The room can only be handed to a user".to_string(),
+        ))?;
+
+    if payload.require_acceptance {
+        collection
+            .update_one(
+                doc! {"_id": room.id},
+                doc! { "$set": { "pending_owner": target.id } },
+            )
+            .await
+            .map_err(|e| {
+                println!("Some error occurred: {e}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error".to_string(),
+                )
+            })?;
+
+        return Ok(format!(
+            "{} was offered the ownership of {}",
+            target.name, room.name
+        ));
+    }
+
+    hand_over(&db, &room, target.id).await?;
+
+    Ok(format!("{} is now the owner of {}", target.name, room.name))
+}
+
+pub async fn accept_ownership(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let room = find_offered_room(&db, &room_id, claims.user_id).await?;
+
+    // The offer is void if the user left the room in the meantime
+    if !policy::is_member(&db, room.id, claims.user_id).await? {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You are no longer part of the room".to_string(),
+        ));
+    }
+
+    hand_over(&db, &room, claims.user_id).await?;
+
+    Ok(format!("You are now the owner of {}", room.name))
+}
+
+// The offered user declines, or the owner withdraws the offer
+pub async fn decline_ownership(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<Room> = db.collection("room");
+
+    let room_obj_id = ObjectId::parse_str(&room_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
+
+    let result = collection
+        .update_one(
+            doc! {
+                "_id": room_obj_id,
+                "$or": [ { "pending_owner": claims.user_id }, { "owner": claims.user_id } ],
+                "pending_owner": { "$exists": true }
+            },
+            doc! { "$unset": { "pending_owner": "" } },
+        )
+        .await
+        .map_err(|e| {
+            println!("Some error occurred: {e}");
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "internal server error".to_string(),
+                "Internal Server Error".to_string(),
             )
         })?;
 
-    return Ok("The room is deleted successfully by its owner".to_string());
+    if result.matched_count == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "There is no ownership offer for you in this room".to_string(),
+        ));
+    }
+
+    Ok("The ownership offer was withdrawn".to_string())
+}
+
+// Called before a user is deleted, every room they own goes to its successor or is
+// deleted when nobody is left to take it
+pub async fn hand_over_owned_rooms(
+    db: &Arc<Database>,
+    user_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<Room> = db.collection("room");
+    let membership_collection: Collection<Membership> = db.collection("membership");
+
+    let rooms: Vec<Room> = collection
+        .find(doc! {"owner": user_id})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    for room in rooms {
+        match successor(db, &room).await? {
+            Some(successor) => {
+                let room_id = room.id;
+                let archived = room.archived_at.is_some();
+
+                // The owner, both memberships and the member count change together
+                let mut session = db.client().start_session().await.map_err(internal_error)?;
+                session
+                    .start_transaction()
+                    .and_run(
+                        (collection.clone(), membership_collection.clone()),
+                        move |session, (collection, membership_collection)| {
+                            async move {
+                                let result = collection
+                                    .update_one(
+                                        doc! {"_id": room_id, "owner": user_id},
+                                        doc! {
+                                            "$set": { "owner": successor },
+                                            "$unset": { "pending_owner": "" }
+                                        },
+                                    )
+                                    .session(&mut *session)
+                                    .await?;
+
+                                // The room was handed over in the meantime
+                                if result.matched_count == 0 {
+                                    return Ok(());
+                                }
+
+                                membership_collection
+                                    .update_one(
+                                        doc! {"room_id": room_id, "user_id": successor},
+                                        doc! { "$set": { "role": RoomRole::Owner.as_str() } },
+                                    )
+                                    .session(&mut *session)
+                                    .await?;
+
+                                // Archived rooms keep their members, the old owner stays
+                                // on as an admin like after a transfer
+                                if archived {
+                                    membership_collection
+                                        .update_one(
+                                            doc! {"room_id": room_id, "user_id": user_id},
+                                            doc! { "$set": { "role": RoomRole::Admin.as_str() } },
+                                        )
+                                        .session(session)
+                                        .await?;
+                                    return Ok(());
+                                }
+
+                                let removed = membership_collection
+                                    .delete_one(doc! {"room_id": room_id, "user_id": user_id})
+                                    .session(&mut *session)
+                                    .await?;
+                                if removed.deleted_count > 0 {
+                                    collection
+                                        .update_one(
+                                            doc! {"_id": room_id},
+                                            doc! { "$inc": { "member_count": -1 } },
+                                        )
+                                        .session(session)
+                                        .await?;
+                                }
+                                Ok(())
+                            }
+                            .boxed()
+                        },
+                    )
+                    .await
+                    .map_err(internal_error)?;
+                println!("The room {} was handed over to {successor}", room.id);
+            }
+            None => {
+                purge_room(db, &room, user_id).await?;
+                println!("The room {} was deleted with its owner", room.id);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Deletes the room with everything that belongs to it and leaves a DeletedRoom behind in
+// one transaction. The messages can be many, so they go in batches in the background after
+// the room is gone. The room's endpoints get room.deleted, the members find the room in
+// /api/room/deleted and requests on the room itself get 410 from in_room
+async fn purge_room(
+    db: &Arc<Database>,
+    room: &Room,
+    actor_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+    let webhook_collection: Collection<OutgoingWebhook> = db.collection("outgoing_webhook");
+
+    let deleted = events::prepare_emit(db, room.id, RoomEvent::RoomDeleted { actor_id })
+        .await
+        .map_err(internal_error)?;
+
+    let webhook_ids = webhook_collection
+        .distinct("_id", doc! {"room_id": room.id})
+        .await
+        .map_err(internal_error)?;
+
+    let record = DeletedRoom {
+        id: room.id,
+        name: room.name.clone(),
+        deleted_by: actor_id,
+        deleted_at: DateTime::now(),
+        members: Vec::new(),
+        messages_deleted: false,
+    };
+    let room_id = room.id;
+
+    let mut session = db.client().start_session().await.map_err(internal_error)?;
+    let purged = session
+        .start_transaction()
+        .and_run(
+            (db.clone(), record, webhook_ids),
+            move |session, (db, record, webhook_ids)| {
+                async move {
+                    let result = db
+                        .collection::<Room>("room")
+                        .delete_one(doc! {"_id": room_id})
+                        .session(&mut *session)
+                        .await?;
+
+                    // Someone else deleted the room first
+                    if result.deleted_count == 0 {
+                        return Ok(false);
+                    }
+
+                    // Everyone to tell, read with the memberships they lose below
+                    let members = db
+                        .collection::<Membership>("membership")
+                        .distinct(
+                            "user_id",
+                            doc! {"room_id": room_id, "user_id": { "$ne": actor_id }, "is_bot": { "$ne": true }},
+                        )
+                        .session(&mut *session)
+                        .await?;
+                    record.members = members.iter().filter_map(Bson::as_object_id).collect();
+
+                    db.collection::<DeletedRoom>("deleted_room")
+                        .insert_one(&*record)
+                        .session(&mut *session)
+                        .await?;
+
+                    for name in [
+                        "membership",
+                        "room_invite",
+                        "join_request",
+                        "room_restriction",
+                        "moderation_log",
+                        "room_change",
+                        "webhook",
+                        "outgoing_webhook",
+                        "external_command",
+                        "slow_mode_slot",
+                    ] {
+                        db.collection::<Document>(name)
+                            .delete_many(doc! {"room_id": room_id})
+                            .session(&mut *session)
+                            .await?;
+                    }
+
+                    db.collection::<WebhookDelivery>("webhook_delivery")
+                        .delete_many(doc! {"webhook_id": { "$in": webhook_ids.clone() }})
+                        .session(&mut *session)
+                        .await?;
+
+                    db.collection::<Workspace>("workspace")
+                        .update_many(
+                            doc! {"default_rooms": room_id},
+                            doc! { "$pull": { "default_rooms": room_id } },
+                        )
+                        .session(session)
+                        .await?;
+                    Ok(true)
+                }
+                .boxed()
+            },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    if !purged {
+        return Ok(());
+    }
+
+    deleted.send();
+
+    let db = db.clone();
+    tokio::spawn(async move { delete_room_messages_with_retries(&db, room_id).await });
+
+    Ok(())
+}
+
+// A room still failing after the last attempt is left to the sweeper
+async fn delete_room_messages_with_retries(db: &Database, room_id: ObjectId) {
+    let mut delay = PURGE_FIRST_RETRY_DELAY;
+    for attempt in 1..=PURGE_MAX_ATTEMPTS {
+        match delete_room_messages(db, room_id).await {
+            Ok(()) => return,
+            Err(e) => println!(
+                "Failed to delete the messages of the room {room_id}, attempt {attempt}: {e}"
+            ),
+        }
+        if attempt < PURGE_MAX_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
+// Deletes the messages of a deleted room a batch at a time, along with their nonces
+async fn delete_room_messages(
+    db: &Database,
+    room_id: ObjectId,
+) -> Result<(), mongodb::error::Error> {
+    let message_collection: Collection<Document> = db.collection("message");
+    let nonce_collection: Collection<MessageNonce> = db.collection("message_nonce");
+    let deleted_collection: Collection<DeletedRoom> = db.collection("deleted_room");
+
+    loop {
+        let batch: Vec<Document> = message_collection
+            .find(doc! {"room_id": room_id})
+            .projection(doc! {"_id": 1})
+            .limit(PURGE_BATCH_SIZE)
+            .await?
+            .try_collect()
+            .await?;
+
+        if batch.is_empty() {
+            break;
+        }
+
+        let ids: Vec<Bson> = batch
+            .into_iter()
+            .filter_map(|message| message.get("_id").cloned())
+            .collect();
+        message_collection
+            .delete_many(doc! {"_id": { "$in": ids }})
+            .await?;
+    }
+
+    nonce_collection
+        .delete_many(doc! {"room_id": room_id})
+        .await?;
+
+    deleted_collection
+        .update_one(
+            doc! {"_id": room_id},
+            doc! { "$set": { "messages_deleted": true } },
+        )
+        .await?;
+
+    Ok(())
 }
 
+// Finishes deleting the messages of rooms whose purge a restart or a failure cut short,
+// once at startup and then every PURGE_SWEEP_INTERVAL for as long as the server runs
+pub async fn sweep_room_purges(db: Arc<Database>) {
+    let mut interval = tokio::time::interval(PURGE_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = resume_room_purges(&db).await {
+            println!("Failed to look up the unfinished room deletions: {e}");
+        }
+    }
+}
+
+async fn resume_room_purges(db: &Database) -> Result<(), mongodb::error::Error> {
+    let deleted_collection: Collection<DeletedRoom> = db.collection("deleted_room");
+
+    let room_ids = deleted_collection
+        .distinct("_id", doc! {"messages_deleted": false})
+        .await?;
+
+    // One room failing doesn't hold up the others, it is tried again on the next sweep
+    for room_id in room_ids.iter().filter_map(|id| id.as_object_id()) {
+        match delete_room_messages(db, room_id).await {
+            Ok(()) => println!("Finished deleting the messages of the room {room_id}"),
+            Err(e) => println!("Failed to delete the messages of the room {room_id}: {e}"),
+        }
+    }
+    Ok(())
+}
+
+// The old owner stays in the room as an admin
+async fn hand_over(
+    db: &Database,
+    room: &Room,
+    new_owner: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<Room> = db.collection("room");
+    let membership_collection: Collection<Membership> = db.collection("membership");
+
+    // The owner field and both roles change together
+    let (room_id, old_owner) = (room.id, room.owner);
+    let mut session = db.client().start_session().await.map_err(internal_error)?;
+    let handed_over = session
+        .start_transaction()
+        .and_run(
+            (collection, membership_collection),
+            move |session, (collection, membership_collection)| {
+                async move {
+                    let result = collection
+                        .update_one(
+                            doc! {"_id": room_id, "owner": old_owner},
+                            doc! {
+                                "$set": { "owner": new_owner },
+                                "$unset": { "pending_owner": "" }
+                            },
+                        )
+                        .session(&mut *session)
+                        .await?;
+
+                    // Someone else changed the owner first
+                    if result.matched_count == 0 {
+                        return Ok(false);
+                    }
+
+                    for (user_id, role) in
+                        [(old_owner, RoomRole::Admin), (new_owner, RoomRole::Owner)]
+                    {
+                        membership_collection
+                            .update_one(
+                                doc! {"room_id": room_id, "user_id": user_id},
+                                doc! { "$set": { "role": role.as_str() } },
+                            )
+                            .session(&mut *session)
+                            .await?;
+                    }
+                    Ok(true)
+                }
+                .boxed()
+            },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    if !handed_over {
+        return Err((
+            StatusCode::CONFLICT,
+            "The owner of the room changed, try again".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn find_offered_room(
+    db: &Database,
+    room_id: &str,
+    user_id: ObjectId,
+) -> Result<Room, (StatusCode, String)> {
+    let collection: Collection<Room> = db.collection("room");
+
+    let room_obj_id = ObjectId::parse_str(room_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
+
+    collection
+        .find_one(doc! {"_id": room_obj_id, "pending_owner": user_id})
+        .await
+        .map_err(|e| {
+            println!("Some error occurred: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            "There is no ownership offer for you in this room".to_string(),
+        ))
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
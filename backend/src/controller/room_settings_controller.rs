@@ -0,0 +1,405 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use bson::{Bson, Document, doc, oid::ObjectId};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::Deserialize;
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    events::{self, RoomEvent},
+    middleware::auth_middleware::Claims,
+    models::room_model::{FieldChange, Room, RoomChange, RoomVisibility},
+    policy::{MAX_SLOW_MODE_SECS, Permission, find_room},
+};
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_TOPIC_LEN: usize = 250;
+const MAX_DESCRIPTION_LEN: usize = 1000;
+const MAX_AVATAR_LEN: usize = 2048;
+const MAX_TAGS: usize = 10;
+const MAX_TAG_LEN: usize = 30;
+// Most recent changes returned by the history
+const CHANGE_LOG_LIMIT: i64 = 50;
+
+// DTOs
+// Fields left out are not touched, an empty string or list clears the field
+#[derive(Deserialize, Default)]
+pub struct RoomSettings {
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub description: Option<String>,
+    pub avatar: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub visibility: Option<RoomVisibility>,
+    // Zero turns slow mode off
+    pub slow_mode_secs: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct VisibilityRequest {
+    visibility: RoomVisibility,
+}
+
+pub async fn update_room_settings(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+    Json(payload): Json<RoomSettings>,
+) -> Result<String, (StatusCode, String)> {
+    let room = find_room(&db, &room_id, claims.user_id, Permission::EditSettings).await?;
+
+    let changed = apply_settings(&db, &room, claims.user_id, payload).await?;
+
+    if changed.is_empty() {
+        return Ok("Nothing to update".to_string());
+    }
+
+    Ok(format!("Updated the {}", changed.join(", ")))
+}
+
+// The endpoint from before the settings update, kept for existing clients
+pub async fn set_room_visibility(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+    Json(payload): Json<VisibilityRequest>,
+) -> Result<String, (StatusCode, String)> {
+    let room = find_room(&db, &room_id, claims.user_id, Permission::EditSettings).await?;
+
+    let settings = RoomSettings {
+        visibility: Some(payload.visibility),
+        ..Default::default()
+    };
+    apply_settings(&db, &room, claims.user_id, settings).await?;
+
+    Ok("The visibility of the room was updated".to_string())
+}
+
+pub async fn get_room_changes(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<RoomChange>>, (StatusCode, String)> {
+    let collection: Collection<RoomChange> = db.collection("room_change");
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::EditSettings).await?;
+
+    let changes: Vec<RoomChange> = collection
+        .find(doc! {"room_id": room.id})
+        .sort(doc! {"created_at": -1})
+        .limit(CHANGE_LOG_LIMIT)
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(changes))
+}
+
+pub async fn archive_room(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let room = find_room(&db, &room_id, claims.user_id, Permission::ArchiveRoom).await?;
+
+    set_archived(&db, &room, claims.user_id, true).await?;
+
+    Ok(format!("{} was archived", room.name))
+}
+
+pub async fn unarchive_room(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let room = find_room(&db, &room_id, claims.user_id, Permission::ArchiveRoom).await?;
+
+    set_archived(&db, &room, claims.user_id, false).await?;
+
+    Ok(format!("{} is open again", room.name))
+}
+
+// Validates the settings, stores the fields that changed and records the change.
+// The caller checks the permission, /topic goes through here as well
+pub async fn apply_settings(
+    db: &Arc<Database>,
+    room: &Room,
+    actor_id: ObjectId,
+    settings: RoomSettings,
+) -> Result<Vec<String>, (StatusCode, String)> {
+    let room_collection: Collection<Room> = db.collection("room");
+    let change_collection: Collection<RoomChange> = db.collection("room_change");
+
+    let mut changes = Vec::new();
+
+    if let Some(name) = settings.name {
+        let name = name.trim().to_string();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("The name must be 1 to {MAX_NAME_LEN} characters"),
+            ));
+        }
+        push_change(&mut changes, "name", Some(room.name.clone()), Some(name));
+    }
+
+    if let Some(topic) = settings.topic {
+        let topic = check_text("topic", topic, MAX_TOPIC_LEN)?;
+        push_change(&mut changes, "topic", room.topic.clone(), topic);
+    }
+
+    if let Some(description) = settings.description {
+        let description = check_text("description", description, MAX_DESCRIPTION_LEN)?;
+        push_change(
+            &mut changes,
+            "description",
+            room.description.clone(),
+            description,
+        );
+    }
+
+    if let Some(avatar) = settings.avatar {
+        let avatar = check_text("avatar", avatar, MAX_AVATAR_LEN)?;
+        if let Some(url) = &avatar {
+            match reqwest::Url::parse(url) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                _ => {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        "The avatar must be a valid http or https url".to_string(),
+                    ));
+                }
+            }
+        }
+        push_change(&mut changes, "avatar", room.avatar.clone(), avatar);
+    }
+
+    if let Some(tags) = settings.tags {
+        let tags = check_tags(tags)?;
+        let old = (!room.tags.is_empty()).then(|| room.tags.clone());
+        let new = (!tags.is_empty()).then_some(tags);
+        push_change(&mut changes, "tags", old, new);
+    }
+
+    if let Some(visibility) = settings.visibility {
+        push_change(
+            &mut changes,
+            "visibility",
+            Some(room.visibility.as_str()),
+            Some(visibility.as_str()),
+        );
+    }
+
+    if let Some(secs) = settings.slow_mode_secs {
+        if secs > MAX_SLOW_MODE_SECS {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Slow mode can be at most {MAX_SLOW_MODE_SECS} seconds"),
+            ));
+        }
+        let secs = (secs > 0).then_some(secs);
+        push_change(&mut changes, "slow_mode_secs", room.slow_mode_secs, secs);
+    }
+
+    if changes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let now = DateTime::now();
+    let mut set = doc! { "updated_at": now };
+    let mut unset = Document::new();
+    for change in &changes {
+        match &change.new {
+            Bson::Null => unset.insert(change.field.clone(), ""),
+            value => set.insert(change.field.clone(), value.clone()),
+        };
+    }
+
+    let mut update = doc! { "$set": set };
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
+
+    room_collection
+        .update_one(doc! {"_id": room.id}, update)
+        .await
+        .map_err(internal_error)?;
+
+    let fields: Vec<String> = changes.iter().map(|change| change.field.clone()).collect();
+
+    let record = RoomChange {
+        id: ObjectId::new(),
+        room_id: room.id,
+        actor_id,
+        changes,
+        created_at: now,
+    };
+
+    if let Err(e) = change_collection.insert_one(&record).await {
+        println!("Failed to record the room change: {e}");
+    }
+
+    events::emit(
+        db,
+        room.id,
+        RoomEvent::RoomUpdated {
+            fields: fields.clone(),
+            actor_id,
+        },
+    );
+
+    Ok(fields)
+}
+
+// Flips the archived state and records it like any other settings change
+async fn set_archived(
+    db: &Arc<Database>,
+    room: &Room,
+    actor_id: ObjectId,
+    archived: bool,
+) -> Result<(), (StatusCode, String)> {
+    let room_collection: Collection<Room> = db.collection("room");
+    let change_collection: Collection<RoomChange> = db.collection("room_change");
+
+    let now = DateTime::now();
+    let (filter, update) = if archived {
+        (
+            doc! {"_id": room.id, "archived_at": { "$exists": false }},
+            doc! { "$set": { "archived_at": now, "archived_by": actor_id, "updated_at": now } },
+        )
+    } else {
+        (
+            doc! {"_id": room.id, "archived_at": { "$exists": true }},
+            doc! {
+                "$set": { "updated_at": now },
+                "$unset": { "archived_at": "", "archived_by": "" }
+            },
+        )
+    };
+
+    let result = room_collection
+        .update_one(filter, update)
+        .await
+        .map_err(internal_error)?;
+
+    // Checked on the update, so two owners racing can't both record it
+    if result.matched_count == 0 {
+        let state = if archived { "already" } else { "not" };
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The room is {state} archived"),
+        ));
+    }
+
+    let record = RoomChange {
+        id: ObjectId::new(),
+        room_id: room.id,
+        actor_id,
+        changes: vec![FieldChange {
+            field: "archived".to_string(),
+            old: Bson::Boolean(!archived),
+            new: Bson::Boolean(archived),
+        }],
+        created_at: now,
+    };
+
+    if let Err(e) = change_collection.insert_one(&record).await {
+        println!("Failed to record the room change: {e}");
+    }
+
+    events::emit(
+        db,
+        room.id,
+        RoomEvent::RoomUpdated {
+            fields: vec!["archived".to_string()],
+            actor_id,
+        },
+    );
+
+    Ok(())
+}
+
+// Only keeps the fields whose value differs
+fn push_change<T: Into<Bson> + PartialEq>(
+    changes: &mut Vec<FieldChange>,
+    field: &str,
+    old: Option<T>,
+    new: Option<T>,
+) {
+    if old == new {
+        return;
+    }
+
+    changes.push(FieldChange {
+        field: field.to_string(),
+        old: old.map(Into::into).unwrap_or(Bson::Null),
+        new: new.map(Into::into).unwrap_or(Bson::Null),
+    });
+}
+
+// Trims the text, an empty one clears the field
+fn check_text(
+    field: &str,
+    text: String,
+    max_len: usize,
+) -> Result<Option<String>, (StatusCode, String)> {
+    let text = text.trim().to_string();
+
+    if text.chars().count() > max_len {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The {field} can be at most {max_len} characters"),
+        ));
+    }
+
+    Ok((!text.is_empty()).then_some(text))
+}
+
+// Tags are stored in lowercase without duplicates, so filtering by tag is exact
+fn check_tags(tags: Vec<String>) -> Result<Vec<String>, (StatusCode, String)> {
+    let mut cleaned: Vec<String> = Vec::new();
+
+    for tag in tags {
+        let tag = tag.trim().trim_start_matches('#').to_lowercase();
+
+        if tag.is_empty()
+            || tag.chars().count() > MAX_TAG_LEN
+            || !tag
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Tags must be 1 to {MAX_TAG_LEN} letters, digits, - or _"),
+            ));
+        }
+
+        if !cleaned.contains(&tag) {
+            cleaned.push(tag);
+        }
+    }
+
+    if cleaned.len() > MAX_TAGS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("A room can have at most {MAX_TAGS} tags"),
+        ));
+    }
+
+    Ok(cleaned)
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
@@ -3,23 +3,68 @@ use axum::{
     extract::{Path, State},
     http::StatusCode,
 };
-use bson::{doc, oid::ObjectId};
+use bcrypt::verify;
+use bson::{Document, doc, oid::ObjectId};
+use futures_util::TryStreamExt;
 use futures_util::stream::StreamExt;
-use mongodb::{Collection, Database};
-use serde::Serialize;
-use std::sync::Arc;
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::{Deserialize, Serialize};
+use std::{env, sync::Arc};
 
 //crates
-use crate::models::user_model::User;
+use crate::controller::bot_controller::remove_bot;
+use crate::controller::group_dm_controller::remove_from_group_dms;
+use crate::controller::member_controller::remove_member;
+use crate::controller::room_controller::hand_over_owned_rooms;
+use crate::controller::workspace_controller::{hand_over_owned_workspaces, workspaces_of};
+use crate::events::{self, RoomEvent};
+use crate::middleware::auth_middleware::Claims;
+use crate::models::{
+    bot_model::Bot,
+    membership_model::Membership,
+    message_model::Message,
+    room_model::Room,
+    user_model::{DELETED_USER_ID, DELETED_USER_NAME, User},
+};
+
+#[derive(Deserialize)]
+pub struct DeleteAccountRequest {
+    // Only needed when deleting your own account
+    password: String,
+}
+
+// What happens to the messages of a deleted account, set with DELETED_USER_MESSAGES
+#[derive(Clone, Copy, PartialEq)]
+enum MessagePolicy {
+    Anonymize,
+    Purge,
+}
+
+impl MessagePolicy {
+    fn from_env() -> Self {
+        match env::var("DELETED_USER_MESSAGES").as_deref() {
+            Ok("purge") => MessagePolicy::Purge,
+            Ok("anonymize") | Err(_) => MessagePolicy::Anonymize,
+            Ok(other) => {
+                println!("Unknown DELETED_USER_MESSAGES value {other}, anonymizing");
+                MessagePolicy::Anonymize
+            }
+        }
+    }
+}
 
 #[derive(Serialize)]
 pub struct UserResponse {
     pub name: String,
-    pub email: String,
+    // Bots have no email
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub is_bot: bool,
 }
 
 pub async fn get_user_by_id(
     State(db): State<Arc<Database>>,
+    claims: Claims,
     Path(id): Path<String>,
 ) -> Result<Json<UserResponse>, (StatusCode, String)> {
     let collection: Collection<User> = db.collection("user");
@@ -31,39 +76,68 @@ pub async fn get_user_by_id(
     let obj_id = ObjectId::parse_str(&id)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid ID format".to_string()))?;
 
-    let filter = doc! {
-        "_id": &obj_id
+    let mut pipeline = vec![doc! { "$match": { "_id": &obj_id } }];
+    pipeline.extend(visible_users(&db, claims.user_id).await?);
+
+    let user = match collection.aggregate(pipeline).with_type::<User>().await {
+        Ok(mut cursor) => cursor.try_next().await,
+        Err(e) => Err(e),
     };
 
-    match collection.find_one(filter).await {
+    match user {
         Ok(Some(user_found)) => {
-            return Ok(Json(UserResponse {
+            Ok(Json(UserResponse {
                 name: user_found.name,
-                email: user_found.email,
-            }));
+                email: Some(user_found.email),
+                is_bot: false,
+            }))
         }
         Ok(None) => {
-            return Err((
-                StatusCode::NOT_FOUND,
-                "No user is present associated with the given id".to_string(),
-            ));
+            // The id may belong to a bot, which lives in its own collection
+            let bot_collection: Collection<Bot> = db.collection("bot");
+            match bot_collection.find_one(doc! {"_id": &obj_id}).await {
+                Ok(Some(bot)) => {
+                    Ok(Json(UserResponse {
+                        name: bot.name,
+                        email: None,
+                        is_bot: true,
+                    }))
+                }
+                Ok(None) => {
+                    Err((
+                        StatusCode::NOT_FOUND,
+                        "No user is present associated with the given id".to_string(),
+                    ))
+                }
+                Err(e) => {
+                    println!("Some err occured: {}", e);
+                    Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Internal Server Error".to_string(),
+                    ))
+                }
+            }
         }
         Err(e) => {
             println!("Some err occured: {}", e);
-            return Err((
+            Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal Server Error".to_string(),
-            ));
+            ))
         }
     }
 }
 
 pub async fn get_all_user(
     State(db): State<Arc<Database>>,
+    claims: Claims,
 ) -> Result<Json<Vec<UserResponse>>, (StatusCode, String)> {
     let collection: Collection<User> = db.collection("user");
 
-    let mut cursor = match collection.find(doc! {}).await {
+    let mut pipeline = visible_users(&db, claims.user_id).await?;
+    pipeline.push(doc! { "$sort": { "name": 1 } });
+
+    let mut cursor = match collection.aggregate(pipeline).with_type::<User>().await {
         Ok(cursor) => cursor,
         Err(e) => {
             println!("Some Error in Database: {}", e);
@@ -81,7 +155,8 @@ pub async fn get_all_user(
             Ok(user) => {
                 users.push(UserResponse {
                     name: user.name,
-                    email: user.email,
+                    email: Some(user.email),
+                    is_bot: false,
                 });
             }
             Err(e) => {
@@ -92,8 +167,10 @@ pub async fn get_all_user(
     Ok(Json(users))
 }
 
+// Exact name matches among the users the caller can see
 pub async fn search_by_name(
     State(db): State<Arc<Database>>,
+    claims: Claims,
     Path(name): Path<String>,
 ) -> Result<Json<Vec<UserResponse>>, (StatusCode, String)> {
     let collection: Collection<User> = db.collection("user");
@@ -101,11 +178,10 @@ pub async fn search_by_name(
         return Err((StatusCode::BAD_REQUEST, "Name is not given".to_string()));
     }
 
-    let filter = doc! {
-        "name": &name
-    };
+    let mut pipeline = vec![doc! { "$match": { "name": &name } }];
+    pipeline.extend(visible_users(&db, claims.user_id).await?);
 
-    let mut cursor = match collection.find(filter).await {
+    let mut cursor = match collection.aggregate(pipeline).with_type::<User>().await {
         Ok(cursor) => cursor,
         Err(e) => {
             println!("Some error occured: {}", e);
@@ -123,7 +199,8 @@ pub async fn search_by_name(
             Ok(user) => {
                 users.push(UserResponse {
                     name: user.name,
-                    email: user.email,
+                    email: Some(user.email),
+                    is_bot: false,
                 });
             }
             Err(e) => {
@@ -135,28 +212,256 @@ pub async fn search_by_name(
     Ok(Json(users))
 }
 
+// Stages that keep the caller, the people sharing a workspace with them and the ones
+// outside of any. The workspaces are looked up per user, not for the whole server
+async fn visible_users(
+    db: &Database,
+    user_id: ObjectId,
+) -> Result<Vec<Document>, (StatusCode, String)> {
+    let my_workspaces = workspaces_of(db, user_id).await?;
+
+    Ok(vec![
+        doc! { "$lookup": {
+            "from": "workspace_member",
+            "localField": "_id",
+            "foreignField": "user_id",
+            "as": "workspaces",
+        } },
+        doc! { "$match": { "$or": [
+            { "_id": user_id },
+            { "workspaces": { "$size": 0 } },
+            { "workspaces.workspace_id": { "$in": my_workspaces } }
+        ] } },
+        doc! { "$project": { "workspaces": 0 } },
+    ])
+}
+
+// Users delete their own account with their password, server admins can delete any account
 pub async fn delete_user(
     State(db): State<Arc<Database>>,
+    claims: Claims,
     Path(id): Path<String>,
+    payload: Option<Json<DeleteAccountRequest>>,
 ) -> Result<String, (StatusCode, String)> {
     let collection: Collection<User> = db.collection("user");
 
     let obj_id = ObjectId::parse_str(id)
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid ID format".to_string()))?;
 
-    match collection.find_one_and_delete(doc! {"_id": obj_id}).await {
-        Ok(Some(user_found)) => return Ok(
-            format!("The User with user id: {}, is deleted successfully", user_found.id)),
-        Ok(None) => {
-            println!("There are no users with this user id");
-            return Err((StatusCode::NOT_FOUND, "User Not Found".to_string()));
+    let user = collection
+        .find_one(doc! {"_id": obj_id})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "User Not Found".to_string()))?;
+
+    if obj_id == claims.user_id {
+        let password = payload.map(|Json(request)| request.password).unwrap_or_default();
+        if !verify(&password, &user.password).unwrap_or(false) {
+            return Err((StatusCode::UNAUTHORIZED, "Invalid password".to_string()));
         }
-        Err(e) => {
-            println!("Database Error: {}", e);
+    } else {
+        let is_admin = collection
+            .find_one(doc! {"_id": claims.user_id})
+            .await
+            .map_err(internal_error)?
+            .is_some_and(|caller| caller.is_admin);
+        if !is_admin {
             return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal Server Error".to_string(),
+                StatusCode::FORBIDDEN,
+                "You can only delete your own account".to_string(),
             ));
         }
     }
+
+    // Marked first, so an interrupted deletion is picked up again at the next start
+    collection
+        .update_one(
+            doc! {"_id": obj_id, "deleting_at": null},
+            doc! { "$set": { "deleting_at": DateTime::now() } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    delete_account(&db, obj_id).await?;
+
+    Ok(format!(
+        "The User with user id: {}, is deleted successfully",
+        user.id
+    ))
+}
+
+// Finishes the deletions a restart or an error cut short
+pub async fn resume_account_deletions(db: &Arc<Database>) -> Result<(), mongodb::error::Error> {
+    let collection: Collection<User> = db.collection("user");
+
+    let user_ids = collection
+        .distinct("_id", doc! {"deleting_at": { "$ne": null }})
+        .await?;
+
+    for user_id in user_ids.iter().filter_map(|id| id.as_object_id()) {
+        match delete_account(db, user_id).await {
+            Ok(()) => println!("Finished deleting the account {user_id}"),
+            Err((_, msg)) => println!("Failed to finish deleting the account {user_id}: {msg}"),
+        }
+    }
+    Ok(())
+}
+
+// Removes everything of the account, then the account itself. Every step can run again,
+// so a deletion that stopped half way is finished by running it once more
+async fn delete_account(db: &Arc<Database>, user_id: ObjectId) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<User> = db.collection("user");
+    let membership_collection: Collection<Membership> = db.collection("membership");
+    let room_collection: Collection<Room> = db.collection("room");
+    let bot_collection: Collection<Bot> = db.collection("bot");
+
+    // Rooms and workspaces can't be left without an owner
+    hand_over_owned_rooms(db, user_id).await?;
+    hand_over_owned_workspaces(db, user_id).await?;
+
+    // Every room, archived ones too, so unarchiving doesn't bring the account back
+    let joined = membership_collection
+        .distinct("room_id", doc! {"user_id": user_id})
+        .await
+        .map_err(internal_error)?;
+    for room_id in joined.iter().filter_map(|id| id.as_object_id()) {
+        if remove_member(db, room_id, user_id).await? {
+            events::emit(
+                db,
+                room_id,
+                RoomEvent::MemberLeft {
+                    user_id,
+                    actor_id: user_id,
+                },
+            );
+        }
+    }
+    room_collection
+        .update_many(
+            doc! {"pending_owner": user_id},
+            doc! { "$unset": { "pending_owner": "" } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    let bots: Vec<Bot> = bot_collection
+        .find(doc! {"owner": user_id})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+    for bot in &bots {
+        remove_bot(db, bot).await?;
+    }
+
+    remove_from_group_dms(db, user_id).await?;
+
+    // The integrations and invites they set up stop with them
+    for (name, filter) in [
+        ("join_request", doc! {"user_id": user_id}),
+        ("room_restriction", doc! {"user_id": user_id}),
+        ("message_nonce", doc! {"sender_id": user_id}),
+        ("slow_mode_slot", doc! {"sender_id": user_id}),
+        ("room_invite", doc! {"created_by": user_id}),
+        ("external_command", doc! {"created_by": user_id}),
+        ("webhook", doc! {"created_by": user_id}),
+        ("outgoing_webhook", doc! {"created_by": user_id}),
+    ] {
+        db.collection::<Document>(name)
+            .delete_many(filter)
+            .await
+            .map_err(internal_error)?;
+    }
+    db.collection::<Document>("deleted_room")
+        .update_many(
+            doc! {"members": user_id},
+            doc! { "$pull": { "members": user_id } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    clean_up_messages(db, user_id, MessagePolicy::from_env()).await?;
+
+    collection
+        .delete_one(doc! {"_id": user_id})
+        .await
+        .map_err(internal_error)?;
+
+    Ok(())
+}
+
+// Votes are always taken back. Anonymizing keeps the messages under a placeholder author,
+// purging deletes them and blanks the quotes of them
+async fn clean_up_messages(
+    db: &Database,
+    user_id: ObjectId,
+    policy: MessagePolicy,
+) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<Message> = db.collection("message");
+
+    collection
+        .update_many(
+            doc! {"poll.options.voters": user_id},
+            doc! { "$pull": { "poll.options.$[].voters": user_id } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    match policy {
+        MessagePolicy::Anonymize => {
+            collection
+                .update_many(
+                    doc! {"sender_id": user_id},
+                    doc! { "$set": { "sender_id": DELETED_USER_ID } },
+                )
+                .await
+                .map_err(internal_error)?;
+        }
+        MessagePolicy::Purge => {
+            collection
+                .delete_many(doc! {"sender_id": user_id})
+                .await
+                .map_err(internal_error)?;
+        }
+    }
+
+    // The other side of their direct messages keeps the conversation
+    collection
+        .update_many(
+            doc! {"receiver_id": user_id},
+            doc! { "$set": { "receiver_id": DELETED_USER_ID } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    collection
+        .update_many(
+            doc! {"forwarded_from.sender_id": user_id},
+            doc! { "$set": { "forwarded_from.sender_id": DELETED_USER_ID } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    let mut quote = doc! {
+        "reply_to.sender_id": DELETED_USER_ID,
+        "reply_to.sender_name": DELETED_USER_NAME,
+    };
+    if policy == MessagePolicy::Purge {
+        quote.insert("reply_to.content", "This message was deleted");
+    }
+    collection
+        .update_many(doc! {"reply_to.sender_id": user_id}, doc! { "$set": quote })
+        .await
+        .map_err(internal_error)?;
+
+    Ok(())
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
 }
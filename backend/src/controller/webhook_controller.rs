@@ -0,0 +1,690 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use bson::{doc, oid::ObjectId};
+use futures_util::{StreamExt, TryStreamExt};
+use mongodb::{Collection, Database, bson::DateTime, options::ReturnDocument};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    controller::message_controller::{PostError, take_slow_mode},
+    events::{self, EVENT_NAMES, RoomEvent},
+    middleware::auth_middleware::Claims,
+    models::{
+        message_model::{BotSender, Message},
+        room_model::{Room, RoomRole},
+        webhook_model::{IncomingWebhook, OutgoingWebhook, WebhookDelivery, WebhookRateBucket},
+    },
+    policy::{self, Permission, find_room},
+    utils::{
+        db::is_duplicate_key_error,
+        http::check_public_url,
+        token::{generate_token, hash_token},
+    },
+};
+
+// Messages a single webhook may post per minute
+const WEBHOOK_RATE_LIMIT: u64 = 30;
+const MAX_WEBHOOK_MESSAGE_LEN: usize = 4000;
+// Deliveries returned by the delivery log endpoint
+const DELIVERY_LOG_LIMIT: i64 = 50;
+
+// DTOs
+#[derive(Deserialize)]
+pub struct WebhookRequest {
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct WebhookCreatedResponse {
+    msg: String,
+    id: ObjectId,
+    // Only returned once, the server keeps a hash
+    token: String,
+    url: String,
+}
+
+#[derive(Serialize)]
+pub struct Webhooks {
+    id: ObjectId,
+    room_id: ObjectId,
+    name: String,
+    created_by: ObjectId,
+    created_at: DateTime,
+}
+
+#[derive(Deserialize)]
+pub struct OutgoingWebhookRequest {
+    url: String,
+    #[serde(default)]
+    events: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct OutgoingWebhookCreatedResponse {
+    msg: String,
+    id: ObjectId,
+    // Only returned once, used to verify the X-RustChat-Signature header
+    secret: String,
+}
+
+#[derive(Serialize)]
+pub struct OutgoingWebhooks {
+    id: ObjectId,
+    room_id: ObjectId,
+    url: String,
+    events: Vec<String>,
+    created_by: ObjectId,
+    created_at: DateTime,
+    failure_count: u32,
+    disabled: bool,
+}
+
+#[derive(Serialize)]
+pub struct WebhookMessageResponse {
+    msg: String,
+    id: String,
+}
+
+// Accepts the native format ({"content"}) and a subset of Slack's incoming webhook format
+#[derive(Deserialize)]
+pub struct WebhookPayload {
+    content: Option<String>,
+    text: Option<String>,
+    #[serde(default)]
+    attachments: Vec<SlackAttachment>,
+    #[serde(default)]
+    blocks: Vec<SlackBlock>,
+}
+
+#[derive(Deserialize)]
+pub struct SlackAttachment {
+    fallback: Option<String>,
+    pretext: Option<String>,
+    title: Option<String>,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SlackBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    text: Option<SlackText>,
+    #[serde(default)]
+    fields: Vec<SlackText>,
+    #[serde(default)]
+    elements: Vec<SlackText>,
+}
+
+#[derive(Deserialize)]
+pub struct SlackText {
+    text: Option<String>,
+}
+
+impl WebhookPayload {
+    // Flattens the payload into the plain text stored as the message content
+    fn render(self) -> String {
+        let mut lines = Vec::new();
+
+        if let Some(content) = self.content.or(self.text) {
+            lines.push(content);
+        }
+
+        for block in self.blocks {
+            match block.kind.as_str() {
+                "header" | "section" => {
+                    lines.extend(block.text.and_then(|text| text.text));
+                    lines.extend(block.fields.into_iter().filter_map(|field| field.text));
+                }
+                "context" => {
+                    lines.extend(
+                        block
+                            .elements
+                            .into_iter()
+                            .filter_map(|element| element.text),
+                    );
+                }
+                "divider" => lines.push("---".to_string()),
+                _ => {}
+            }
+        }
+
+        for attachment in self.attachments {
+            lines.extend(attachment.pretext);
+            match (attachment.title, attachment.text) {
+                (None, None) => lines.extend(attachment.fallback),
+                (title, text) => {
+                    lines.extend(title);
+                    lines.extend(text);
+                }
+            }
+        }
+
+        lines
+            .into_iter()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+pub async fn create_webhook(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+    Json(payload): Json<WebhookRequest>,
+) -> Result<Json<WebhookCreatedResponse>, (StatusCode, String)> {
+    let collection: Collection<IncomingWebhook> = db.collection("webhook");
+
+    let name = payload.name.trim().to_string();
+    if name.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Name is required".to_string()));
+    }
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::EditSettings).await?;
+
+    let token = generate_token();
+    let webhook = IncomingWebhook {
+        id: ObjectId::new(),
+        room_id: room.id,
+        name,
+        token_hash: hash_token(&token),
+        created_by: claims.user_id,
+        created_at: DateTime::now(),
+    };
+
+    match collection.insert_one(&webhook).await {
+        Ok(_) => {
+            Ok(Json(WebhookCreatedResponse {
+                msg: format!("The webhook {} was created", webhook.name),
+                id: webhook.id,
+                url: format!("/api/hooks/{}/{}", webhook.id, token),
+                token,
+            }))
+        }
+        Err(e) => {
+            println!("Error in creating the webhook: {e}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ))
+        }
+    }
+}
+
+pub async fn get_room_webhooks(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<Webhooks>>, (StatusCode, String)> {
+    let collection: Collection<IncomingWebhook> = db.collection("webhook");
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::EditSettings).await?;
+
+    let mut cursor = match collection.find(doc! {"room_id": room.id}).await {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            println!("Error in finding the webhooks: {e}");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ));
+        }
+    };
+
+    let mut webhooks = Vec::new();
+
+    while let Some(result) = cursor.next().await {
+        match result {
+            Ok(webhook) => webhooks.push(Webhooks {
+                id: webhook.id,
+                room_id: webhook.room_id,
+                name: webhook.name,
+                created_by: webhook.created_by,
+                created_at: webhook.created_at,
+            }),
+            Err(e) => {
+                println!("Some error occurred: {e}");
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal Server Error".to_string(),
+                ));
+            }
+        }
+    }
+
+    Ok(Json(webhooks))
+}
+
+pub async fn delete_webhook(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<IncomingWebhook> = db.collection("webhook");
+
+    let webhook_obj_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Webhook Id".to_string()))?;
+
+    let webhook = match collection.find_one(doc! {"_id": webhook_obj_id}).await {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "Webhook Not Found".to_string())),
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ));
+        }
+    };
+
+    find_room(&db, &webhook.room_id.to_hex(), claims.user_id, Permission::EditSettings).await?;
+
+    collection
+        .delete_one(doc! {"_id": webhook.id})
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?;
+
+    Ok("The webhook is deleted successfully".to_string())
+}
+
+// Public endpoint, the token in the URL takes the place of a user JWT
+pub async fn post_webhook_message(
+    State(db): State<Arc<Database>>,
+    Path((id, token)): Path<(String, String)>,
+    Json(payload): Json<WebhookPayload>,
+) -> Result<Json<WebhookMessageResponse>, PostError> {
+    let collection: Collection<IncomingWebhook> = db.collection("webhook");
+    let message_collection: Collection<Message> = db.collection("message");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let webhook_obj_id = ObjectId::parse_str(&id)
+        .map_err(|_| (StatusCode::NOT_FOUND, "Webhook Not Found".to_string()))?;
+
+    let webhook = match collection
+        .find_one(doc! {"_id": webhook_obj_id, "token_hash": hash_token(&token)})
+        .await
+    {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "Webhook Not Found".to_string()).into()),
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+                .into());
+        }
+    };
+
+    let room = room_collection
+        .find_one(doc! {"_id": webhook.room_id})
+        .await
+        .map_err(|e| {
+            println!("Some error occurred: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?
+        .ok_or((StatusCode::NOT_FOUND, "Room not found".to_string()))?;
+
+    policy::check_not_archived(&room)?;
+
+    let content = payload.render();
+    if content.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The payload has no text to post".to_string(),
+        )
+            .into());
+    }
+    if content.chars().count() > MAX_WEBHOOK_MESSAGE_LEN {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Messages can be at most {MAX_WEBHOOK_MESSAGE_LEN} characters"),
+        )
+            .into());
+    }
+
+    take_rate_slot(&db, webhook.id).await?;
+    // Webhooks post as members, so slow mode holds them to the same pace
+    let slot = take_slow_mode(&db, &room, webhook.id, RoomRole::Member).await?;
+
+    let new_message = Message {
+        id: ObjectId::new(),
+        sender_id: webhook.id,
+        receiver_id: None,
+        room_id: Some(webhook.room_id),
+        group_id: None,
+        content,
+        timestamp: DateTime::now(),
+        forwarded_from: None,
+        reply_to: None,
+        poll: None,
+        bot: Some(BotSender {
+            bot_id: webhook.id,
+            name: webhook.name,
+        }),
+        pinned: None,
+    };
+
+    match message_collection.insert_one(&new_message).await {
+        Ok(_) => {
+            events::emit(
+                &db,
+                webhook.room_id,
+                RoomEvent::MessageCreated(Box::new(new_message.clone())),
+            );
+            Ok(Json(WebhookMessageResponse {
+                msg: "Message was sent Successfully".to_string(),
+                id: new_message.id.to_hex(),
+            }))
+        }
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            if let Some(slot) = &slot {
+                slot.release(&db).await;
+            }
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+                .into())
+        }
+    }
+}
+
+pub async fn create_outgoing_webhook(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+    Json(payload): Json<OutgoingWebhookRequest>,
+) -> Result<Json<OutgoingWebhookCreatedResponse>, (StatusCode, String)> {
+    let collection: Collection<OutgoingWebhook> = db.collection("outgoing_webhook");
+
+    check_public_url(&payload.url)
+        .await
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg))?;
+
+    if let Some(unknown) = payload
+        .events
+        .iter()
+        .find(|event| !EVENT_NAMES.contains(&event.as_str()))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Unknown event {unknown}, expected one of {}",
+                EVENT_NAMES.join(", ")
+            ),
+        ));
+    }
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::EditSettings).await?;
+
+    let webhook = OutgoingWebhook {
+        id: ObjectId::new(),
+        room_id: room.id,
+        url: payload.url,
+        secret: generate_token(),
+        events: payload.events,
+        created_by: claims.user_id,
+        created_at: DateTime::now(),
+        failure_count: 0,
+        disabled: false,
+    };
+
+    match collection.insert_one(&webhook).await {
+        Ok(_) => {
+            Ok(Json(OutgoingWebhookCreatedResponse {
+                msg: format!("Events for the room will be sent to {}", webhook.url),
+                id: webhook.id,
+                secret: webhook.secret,
+            }))
+        }
+        Err(e) => {
+            println!("Error in creating the webhook: {e}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ))
+        }
+    }
+}
+
+pub async fn get_outgoing_webhooks(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<OutgoingWebhooks>>, (StatusCode, String)> {
+    let collection: Collection<OutgoingWebhook> = db.collection("outgoing_webhook");
+
+    let room = find_room(&db, &room_id, claims.user_id, Permission::EditSettings).await?;
+
+    let webhooks: Vec<OutgoingWebhook> = collection
+        .find(doc! {"room_id": room.id})
+        .await
+        .map_err(|e| {
+            println!("Error in finding the webhooks: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            println!("Some error occurred: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?;
+
+    Ok(Json(
+        webhooks
+            .into_iter()
+            .map(|webhook| OutgoingWebhooks {
+                id: webhook.id,
+                room_id: webhook.room_id,
+                url: webhook.url,
+                events: webhook.events,
+                created_by: webhook.created_by,
+                created_at: webhook.created_at,
+                failure_count: webhook.failure_count,
+                disabled: webhook.disabled,
+            })
+            .collect(),
+    ))
+}
+
+pub async fn get_webhook_deliveries(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<WebhookDelivery>>, (StatusCode, String)> {
+    let delivery_collection: Collection<WebhookDelivery> = db.collection("webhook_delivery");
+
+    let webhook = find_owned_outgoing_webhook(&db, &id, claims.user_id).await?;
+
+    let deliveries: Vec<WebhookDelivery> = delivery_collection
+        .find(doc! {"webhook_id": webhook.id})
+        .sort(doc! {"created_at": -1})
+        .limit(DELIVERY_LOG_LIMIT)
+        .await
+        .map_err(|e| {
+            println!("Error in finding the deliveries: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?
+        .try_collect()
+        .await
+        .map_err(|e| {
+            println!("Some error occurred: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?;
+
+    Ok(Json(deliveries))
+}
+
+pub async fn enable_outgoing_webhook(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<OutgoingWebhook> = db.collection("outgoing_webhook");
+
+    let webhook = find_owned_outgoing_webhook(&db, &id, claims.user_id).await?;
+
+    check_public_url(&webhook.url)
+        .await
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg))?;
+
+    collection
+        .update_one(
+            doc! {"_id": webhook.id},
+            doc! { "$set": { "disabled": false, "failure_count": 0 } },
+        )
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?;
+
+    Ok("The webhook is enabled again".to_string())
+}
+
+pub async fn delete_outgoing_webhook(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<OutgoingWebhook> = db.collection("outgoing_webhook");
+    let delivery_collection: Collection<WebhookDelivery> = db.collection("webhook_delivery");
+
+    let webhook = find_owned_outgoing_webhook(&db, &id, claims.user_id).await?;
+
+    collection
+        .delete_one(doc! {"_id": webhook.id})
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?;
+
+    delivery_collection
+        .delete_many(doc! {"webhook_id": webhook.id})
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            )
+        })?;
+
+    Ok("The webhook is deleted successfully".to_string())
+}
+
+async fn find_owned_outgoing_webhook(
+    db: &Database,
+    id: &str,
+    user_id: ObjectId,
+) -> Result<OutgoingWebhook, (StatusCode, String)> {
+    let collection: Collection<OutgoingWebhook> = db.collection("outgoing_webhook");
+
+    let webhook_obj_id = ObjectId::parse_str(id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Webhook Id".to_string()))?;
+
+    let webhook = match collection.find_one(doc! {"_id": webhook_obj_id}).await {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "Webhook Not Found".to_string())),
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ));
+        }
+    };
+
+    find_room(db, &webhook.room_id.to_hex(), user_id, Permission::EditSettings).await?;
+
+    Ok(webhook)
+}
+
+// Rate limit: counts the post in the bucket of the current minute. The count is taken
+// with $inc, so concurrent posts can't both slip in under the limit
+async fn take_rate_slot(db: &Database, webhook_id: ObjectId) -> Result<(), (StatusCode, String)> {
+    let bucket_collection: Collection<WebhookRateBucket> = db.collection("webhook_rate_bucket");
+
+    let now = DateTime::now().timestamp_millis();
+    let window_start = DateTime::from_millis(now - now.rem_euclid(60_000));
+    let filter = doc! { "webhook_id": webhook_id, "window_start": window_start };
+    let update = doc! {
+        "$inc": { "count": 1 },
+        "$setOnInsert": { "_id": ObjectId::new() }
+    };
+
+    // Two posts opening the same bucket race on the unique index, the loser increments it
+    let mut bucket = None;
+    for _ in 0..2 {
+        match bucket_collection
+            .find_one_and_update(filter.clone(), update.clone())
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await
+        {
+            Ok(found) => {
+                bucket = found;
+                break;
+            }
+            Err(e) if is_duplicate_key_error(&e) => continue,
+            Err(e) => return Err(internal_error(e)),
+        }
+    }
+
+    let count = bucket.map(|bucket| bucket.count).ok_or_else(|| {
+        println!("The rate bucket of webhook {webhook_id} could not be taken");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal Server Error".to_string(),
+        )
+    })?;
+
+    if count > WEBHOOK_RATE_LIMIT {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("The webhook can post {WEBHOOK_RATE_LIMIT} messages per minute"),
+        ));
+    }
+
+    Ok(())
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
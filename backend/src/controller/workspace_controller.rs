@@ -0,0 +1,633 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use bson::{Bson, doc, oid::ObjectId};
+use futures_util::{FutureExt, TryStreamExt};
+use mongodb::{Collection, Database, bson::DateTime};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// Crates
+use crate::{
+    controller::member_controller::{add_member, remove_member},
+    events::{self, RoomEvent},
+    middleware::auth_middleware::Claims,
+    models::{
+        membership_model::Membership,
+        moderation_model::RestrictionKind,
+        room_model::{Room, RoomRole},
+        user_model::User,
+        workspace_model::{Workspace, WorkspaceMember, WorkspaceRole},
+    },
+    policy,
+    utils::db::is_duplicate_key_error,
+};
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_DEFAULT_ROOMS: usize = 20;
+
+// DTOs
+#[derive(Deserialize)]
+pub struct WorkspaceRequest {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct WorkspaceRoleRequest {
+    role: WorkspaceRole,
+}
+
+#[derive(Deserialize)]
+pub struct DefaultRoomsRequest {
+    room_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct WorkspaceResponse {
+    msg: String,
+    workspace_id: ObjectId,
+}
+
+#[derive(Serialize)]
+pub struct Workspaces {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    name: String,
+    owner: ObjectId,
+    default_rooms: Vec<ObjectId>,
+    created_at: DateTime,
+    // The caller's role
+    role: WorkspaceRole,
+}
+
+#[derive(Serialize)]
+pub struct WorkspaceMembers {
+    user_id: ObjectId,
+    name: String,
+    role: WorkspaceRole,
+    joined_at: DateTime,
+}
+
+pub async fn create_workspace(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Json(payload): Json<WorkspaceRequest>,
+) -> Result<Json<WorkspaceResponse>, (StatusCode, String)> {
+    let collection: Collection<Workspace> = db.collection("workspace");
+    let member_collection: Collection<WorkspaceMember> = db.collection("workspace_member");
+
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The name must be 1 to {MAX_NAME_LEN} characters"),
+        ));
+    }
+
+    let now = DateTime::now();
+    let workspace = Workspace {
+        id: ObjectId::new(),
+        name,
+        owner: claims.user_id,
+        default_rooms: Vec::new(),
+        created_at: now,
+    };
+
+    collection
+        .insert_one(&workspace)
+        .await
+        .map_err(internal_error)?;
+
+    member_collection
+        .insert_one(WorkspaceMember {
+            id: ObjectId::new(),
+            workspace_id: workspace.id,
+            user_id: claims.user_id,
+            role: WorkspaceRole::Owner,
+            joined_at: now,
+        })
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(WorkspaceResponse {
+        msg: format!("The workspace {} was created", workspace.name),
+        workspace_id: workspace.id,
+    }))
+}
+
+pub async fn get_my_workspaces(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+) -> Result<Json<Vec<Workspaces>>, (StatusCode, String)> {
+    let collection: Collection<Workspace> = db.collection("workspace");
+    let member_collection: Collection<WorkspaceMember> = db.collection("workspace_member");
+
+    let memberships: Vec<WorkspaceMember> = member_collection
+        .find(doc! {"user_id": claims.user_id})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    let workspace_ids: Vec<ObjectId> = memberships
+        .iter()
+        .map(|membership| membership.workspace_id)
+        .collect();
+    let workspaces: Vec<Workspace> = collection
+        .find(doc! {"_id": { "$in": workspace_ids }})
+        .sort(doc! {"name": 1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(
+        workspaces
+            .into_iter()
+            .filter_map(|workspace| {
+                let role = memberships
+                    .iter()
+                    .find(|membership| membership.workspace_id == workspace.id)?
+                    .role;
+                Some(Workspaces {
+                    id: workspace.id,
+                    name: workspace.name,
+                    owner: workspace.owner,
+                    default_rooms: workspace.default_rooms,
+                    created_at: workspace.created_at,
+                    role,
+                })
+            })
+            .collect(),
+    ))
+}
+
+pub async fn get_workspace_members(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<WorkspaceMembers>>, (StatusCode, String)> {
+    let member_collection: Collection<WorkspaceMember> = db.collection("workspace_member");
+    let user_collection: Collection<User> = db.collection("user");
+
+    let (workspace, _) = find_workspace(&db, &id, claims.user_id, WorkspaceRole::Member).await?;
+
+    let members: Vec<WorkspaceMember> = member_collection
+        .find(doc! {"workspace_id": workspace.id})
+        .sort(doc! {"joined_at": 1})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    let user_ids: Vec<ObjectId> = members.iter().map(|member| member.user_id).collect();
+    let users: Vec<User> = user_collection
+        .find(doc! {"_id": { "$in": user_ids }})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(
+        members
+            .into_iter()
+            .filter_map(|member| {
+                let user = users.iter().find(|user| user.id == member.user_id)?;
+                Some(WorkspaceMembers {
+                    user_id: member.user_id,
+                    name: user.name.clone(),
+                    role: member.role,
+                    joined_at: member.joined_at,
+                })
+            })
+            .collect(),
+    ))
+}
+
+// Admins add people, who then join the default rooms of the workspace
+pub async fn add_workspace_member(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((id, user_id)): Path<(String, String)>,
+) -> Result<String, (StatusCode, String)> {
+    let member_collection: Collection<WorkspaceMember> = db.collection("workspace_member");
+    let user_collection: Collection<User> = db.collection("user");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let user_obj_id = ObjectId::parse_str(&user_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid User Id".to_string()))?;
+
+    let (workspace, _) = find_workspace(&db, &id, claims.user_id, WorkspaceRole::Admin).await?;
+
+    let user = user_collection
+        .find_one(doc! {"_id": user_obj_id})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "User Not Found".to_string()))?;
+
+    let member = WorkspaceMember {
+        id: ObjectId::new(),
+        workspace_id: workspace.id,
+        user_id: user.id,
+        role: WorkspaceRole::Member,
+        joined_at: DateTime::now(),
+    };
+
+    match member_collection.insert_one(&member).await {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key_error(&e) => {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("{} is already in the workspace", user.name),
+            ));
+        }
+        Err(e) => return Err(internal_error(e)),
+    }
+
+    // Default rooms that were deleted, archived or moved out of the workspace are skipped
+    let rooms: Vec<Room> = room_collection
+        .find(doc! {
+            "_id": { "$in": &workspace.default_rooms },
+            "workspace_id": workspace.id,
+            "archived_at": { "$exists": false }
+        })
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    for room in rooms {
+        if policy::check_not_restricted(&db, room.id, user.id, RestrictionKind::Ban)
+            .await
+            .is_err()
+        {
+            continue;
+        }
+
+        if add_member(&db, room.id, user.id, RoomRole::Member, false).await? {
+            events::emit(
+                &db,
+                room.id,
+                RoomEvent::MemberJoined {
+                    user_id: user.id,
+                    actor_id: claims.user_id,
+                },
+            );
+        }
+    }
+
+    Ok(format!("{} was added to {}", user.name, workspace.name))
+}
+
+// Admins remove members below them, members can remove themselves to leave
+pub async fn remove_workspace_member(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((id, user_id)): Path<(String, String)>,
+) -> Result<String, (StatusCode, String)> {
+    let member_collection: Collection<WorkspaceMember> = db.collection("workspace_member");
+    let room_collection: Collection<Room> = db.collection("room");
+    let membership_collection: Collection<Membership> = db.collection("membership");
+
+    let user_obj_id = ObjectId::parse_str(&user_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid User Id".to_string()))?;
+
+    let min_role = if user_obj_id == claims.user_id {
+        WorkspaceRole::Member
+    } else {
+        WorkspaceRole::Admin
+    };
+    let (workspace, actor_role) = find_workspace(&db, &id, claims.user_id, min_role).await?;
+
+    if user_obj_id == workspace.owner {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The owner can't leave the workspace".to_string(),
+        ));
+    }
+
+    let target_role = workspace_role(&db, workspace.id, user_obj_id)
+        .await?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            "The user is not part of the workspace".to_string(),
+        ))?;
+
+    if user_obj_id != claims.user_id && target_role >= actor_role {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You can only remove members below your role".to_string(),
+        ));
+    }
+
+    member_collection
+        .delete_one(doc! {"workspace_id": workspace.id, "user_id": user_obj_id})
+        .await
+        .map_err(internal_error)?;
+
+    // Rooms of the workspace are closed to them from now on, the ones they own
+    // stay with them until they hand them over. Archived rooms keep their members,
+    // the policy keeps them out of those instead
+    let room_ids = room_collection
+        .distinct(
+            "_id",
+            doc! {
+                "workspace_id": workspace.id,
+                "owner": { "$ne": user_obj_id },
+                "archived_at": null
+            },
+        )
+        .await
+        .map_err(internal_error)?;
+    let joined = membership_collection
+        .distinct(
+            "room_id",
+            doc! {"user_id": user_obj_id, "room_id": { "$in": room_ids }},
+        )
+        .await
+        .map_err(internal_error)?;
+
+    for room_id in joined.iter().filter_map(Bson::as_object_id) {
+        if remove_member(&db, room_id, user_obj_id).await? {
+            events::emit(
+                &db,
+                room_id,
+                RoomEvent::MemberLeft {
+                    user_id: user_obj_id,
+                    actor_id: claims.user_id,
+                },
+            );
+        }
+    }
+
+    Ok(format!("The user was removed from {}", workspace.name))
+}
+
+// Only the owner promotes admins, ownership can't be given here
+pub async fn set_workspace_role(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path((id, user_id)): Path<(String, String)>,
+    Json(payload): Json<WorkspaceRoleRequest>,
+) -> Result<String, (StatusCode, String)> {
+    let member_collection: Collection<WorkspaceMember> = db.collection("workspace_member");
+
+    let user_obj_id = ObjectId::parse_str(&user_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid User Id".to_string()))?;
+
+    let (workspace, _) = find_workspace(&db, &id, claims.user_id, WorkspaceRole::Owner).await?;
+
+    if payload.role == WorkspaceRole::Owner || user_obj_id == workspace.owner {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The ownership of a workspace can't be changed".to_string(),
+        ));
+    }
+
+    let result = member_collection
+        .update_one(
+            doc! {"workspace_id": workspace.id, "user_id": user_obj_id},
+            doc! { "$set": { "role": payload.role.as_str() } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    if result.matched_count == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "The user is not part of the workspace".to_string(),
+        ));
+    }
+
+    Ok("The role was updated".to_string())
+}
+
+pub async fn set_default_rooms(
+    State(db): State<Arc<Database>>,
+    claims: Claims,
+    Path(id): Path<String>,
+    Json(payload): Json<DefaultRoomsRequest>,
+) -> Result<String, (StatusCode, String)> {
+    let collection: Collection<Workspace> = db.collection("workspace");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let (workspace, _) = find_workspace(&db, &id, claims.user_id, WorkspaceRole::Admin).await?;
+
+    let mut room_ids: Vec<ObjectId> = Vec::new();
+    for room_id in &payload.room_ids {
+        let room_obj_id = ObjectId::parse_str(room_id)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
+        if !room_ids.contains(&room_obj_id) {
+            room_ids.push(room_obj_id);
+        }
+    }
+
+    if room_ids.len() > MAX_DEFAULT_ROOMS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("A workspace can have at most {MAX_DEFAULT_ROOMS} default rooms"),
+        ));
+    }
+
+    let found = room_collection
+        .count_documents(doc! {"_id": { "$in": &room_ids }, "workspace_id": workspace.id})
+        .await
+        .map_err(internal_error)?;
+
+    if found != room_ids.len() as u64 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Default rooms must belong to the workspace".to_string(),
+        ));
+    }
+
+    collection
+        .update_one(
+            doc! {"_id": workspace.id},
+            doc! { "$set": { "default_rooms": &room_ids } },
+        )
+        .await
+        .map_err(internal_error)?;
+
+    Ok(format!(
+        "{} now has {} default rooms",
+        workspace.name,
+        room_ids.len()
+    ))
+}
+
+// Called before a user is deleted, every workspace they own goes to its longest serving
+// admin, then member. A workspace nobody is left in is deleted and its rooms leave it
+pub async fn hand_over_owned_workspaces(
+    db: &Database,
+    user_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<Workspace> = db.collection("workspace");
+    let member_collection: Collection<WorkspaceMember> = db.collection("workspace_member");
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let workspaces: Vec<Workspace> = collection
+        .find(doc! {"owner": user_id})
+        .await
+        .map_err(internal_error)?
+        .try_collect()
+        .await
+        .map_err(internal_error)?;
+
+    for workspace in workspaces {
+        let mut successor = None;
+        for role in [WorkspaceRole::Admin, WorkspaceRole::Member] {
+            successor = member_collection
+                .find_one(doc! {"workspace_id": workspace.id, "role": role.as_str()})
+                .sort(doc! {"joined_at": 1})
+                .await
+                .map_err(internal_error)?;
+            if successor.is_some() {
+                break;
+            }
+        }
+
+        match successor {
+            Some(successor) => {
+                let workspace_id = workspace.id;
+                let (membership_id, successor_id) = (successor.id, successor.user_id);
+
+                // The owner and the successor's role change together, a retry finds the
+                // workspace still owned by the user otherwise
+                let mut session = db.client().start_session().await.map_err(internal_error)?;
+                session
+                    .start_transaction()
+                    .and_run(
+                        (collection.clone(), member_collection.clone()),
+                        move |session, (collection, member_collection)| {
+                            async move {
+                                let result = collection
+                                    .update_one(
+                                        doc! {"_id": workspace_id, "owner": user_id},
+                                        doc! { "$set": { "owner": successor_id } },
+                                    )
+                                    .session(&mut *session)
+                                    .await?;
+
+                                if result.matched_count == 0 {
+                                    return Ok(());
+                                }
+
+                                member_collection
+                                    .update_one(
+                                        doc! {"_id": membership_id},
+                                        doc! { "$set": { "role": WorkspaceRole::Owner.as_str() } },
+                                    )
+                                    .session(session)
+                                    .await?;
+                                Ok(())
+                            }
+                            .boxed()
+                        },
+                    )
+                    .await
+                    .map_err(internal_error)?;
+                println!(
+                    "The workspace {} was handed over to {}",
+                    workspace.id, successor.user_id
+                );
+            }
+            None => {
+                room_collection
+                    .update_many(
+                        doc! {"workspace_id": workspace.id},
+                        doc! { "$unset": { "workspace_id": "" } },
+                    )
+                    .await
+                    .map_err(internal_error)?;
+                collection
+                    .delete_one(doc! {"_id": workspace.id})
+                    .await
+                    .map_err(internal_error)?;
+                println!("The workspace {} was deleted with its owner", workspace.id);
+            }
+        }
+    }
+
+    member_collection
+        .delete_many(doc! {"user_id": user_id})
+        .await
+        .map_err(internal_error)?;
+
+    Ok(())
+}
+
+// None when the user is not part of the workspace
+pub async fn workspace_role(
+    db: &Database,
+    workspace_id: ObjectId,
+    user_id: ObjectId,
+) -> Result<Option<WorkspaceRole>, (StatusCode, String)> {
+    let member_collection: Collection<WorkspaceMember> = db.collection("workspace_member");
+
+    Ok(member_collection
+        .find_one(doc! {"workspace_id": workspace_id, "user_id": user_id})
+        .await
+        .map_err(internal_error)?
+        .map(|member| member.role))
+}
+
+// The ids of the workspaces the user belongs to, for scoping listings
+pub async fn workspaces_of(
+    db: &Database,
+    user_id: ObjectId,
+) -> Result<Vec<Bson>, (StatusCode, String)> {
+    let member_collection: Collection<WorkspaceMember> = db.collection("workspace_member");
+
+    member_collection
+        .distinct("workspace_id", doc! {"user_id": user_id})
+        .await
+        .map_err(internal_error)
+}
+
+async fn find_workspace(
+    db: &Database,
+    id: &str,
+    user_id: ObjectId,
+    min_role: WorkspaceRole,
+) -> Result<(Workspace, WorkspaceRole), (StatusCode, String)> {
+    let collection: Collection<Workspace> = db.collection("workspace");
+
+    let workspace_obj_id = ObjectId::parse_str(id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Workspace Id".to_string()))?;
+
+    let workspace = collection
+        .find_one(doc! {"_id": workspace_obj_id})
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Workspace Not Found".to_string()))?;
+
+    let role = workspace_role(db, workspace.id, user_id).await?.ok_or((
+        StatusCode::FORBIDDEN,
+        "You are not part of the workspace".to_string(),
+    ))?;
+
+    if role < min_role {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You don't have permission to manage this workspace".to_string(),
+        ));
+    }
+
+    Ok((workspace, role))
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
@@ -0,0 +1,449 @@
+use bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, bson::DateTime, options::ReturnDocument};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+
+// Crates
+use crate::{
+    models::{
+        message_model::Message,
+        room_model::Room,
+        webhook_model::{OutgoingWebhook, WebhookDelivery},
+    },
+    utils::http::{HTTP_CLIENT, check_public_url, sign},
+};
+
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+// An endpoint is disabled after this many failed deliveries in a row
+const MAX_CONSECUTIVE_FAILURES: u32 = 10;
+
+pub const EVENT_NAMES: [&str; 6] = [
+    "message.created",
+    "message.deleted",
+    "member.joined",
+    "member.left",
+    "room.updated",
+    "room.deleted",
+];
+
+pub enum RoomEvent {
+    MessageCreated(Box<Message>),
+    MessageDeleted {
+        message_id: ObjectId,
+        actor_id: ObjectId,
+    },
+    MemberJoined {
+        user_id: ObjectId,
+        actor_id: ObjectId,
+    },
+    MemberLeft {
+        user_id: ObjectId,
+        actor_id: ObjectId,
+    },
+    RoomUpdated {
+        fields: Vec<String>,
+        actor_id: ObjectId,
+    },
+    RoomDeleted {
+        actor_id: ObjectId,
+    },
+}
+
+impl RoomEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoomEvent::MessageCreated(_) => "message.created",
+            RoomEvent::MessageDeleted { .. } => "message.deleted",
+            RoomEvent::MemberJoined { .. } => "member.joined",
+            RoomEvent::MemberLeft { .. } => "member.left",
+            RoomEvent::RoomUpdated { .. } => "room.updated",
+            RoomEvent::RoomDeleted { .. } => "room.deleted",
+        }
+    }
+
+    fn data(&self) -> serde_json::Value {
+        match self {
+            RoomEvent::MessageCreated(message) => json!({ "message": message }),
+            RoomEvent::MessageDeleted {
+                message_id,
+                actor_id,
+            } => json!({ "message_id": message_id.to_hex(), "actor_id": actor_id.to_hex() }),
+            RoomEvent::MemberJoined { user_id, actor_id }
+            | RoomEvent::MemberLeft { user_id, actor_id } => {
+                json!({ "user_id": user_id.to_hex(), "actor_id": actor_id.to_hex() })
+            }
+            RoomEvent::RoomUpdated { fields, actor_id } => {
+                json!({ "fields": fields, "actor_id": actor_id.to_hex() })
+            }
+            RoomEvent::RoomDeleted { actor_id } => json!({ "actor_id": actor_id.to_hex() }),
+        }
+    }
+}
+
+// Sends the event to every endpoint registered on the room, in the background
+pub fn emit(db: &Arc<Database>, room_id: ObjectId, event: RoomEvent) {
+    let db = db.clone();
+    tokio::spawn(async move {
+        // Every room message passes through here, so the room's activity is kept current here
+        if let RoomEvent::MessageCreated(message) = &event
+            && let Err(e) = touch_room(&db, room_id, message.timestamp).await
+        {
+            println!("Failed to update the room activity: {e}");
+        }
+
+        if let Err(e) = deliver_to_room(&db, room_id, event).await {
+            println!("Failed to deliver the room event: {e}");
+        }
+    });
+}
+
+async fn touch_room(
+    db: &Database,
+    room_id: ObjectId,
+    timestamp: DateTime,
+) -> Result<(), mongodb::error::Error> {
+    let collection: Collection<Room> = db.collection("room");
+
+    collection
+        .update_one(
+            doc! {"_id": room_id},
+            doc! { "$max": { "last_activity_at": timestamp } },
+        )
+        .await?;
+
+    Ok(())
+}
+
+// Like emit, for a room that is about to be deleted along with its endpoints. They are
+// looked up now, and the returned event is sent once the room is gone
+pub async fn prepare_emit(
+    db: &Arc<Database>,
+    room_id: ObjectId,
+    event: RoomEvent,
+) -> Result<PendingEvent, mongodb::error::Error> {
+    let webhooks = find_webhooks(db, room_id, event.name()).await?;
+
+    Ok(PendingEvent {
+        db: db.clone(),
+        room_id,
+        event,
+        webhooks,
+    })
+}
+
+pub struct PendingEvent {
+    db: Arc<Database>,
+    room_id: ObjectId,
+    event: RoomEvent,
+    webhooks: Vec<OutgoingWebhook>,
+}
+
+impl PendingEvent {
+    pub fn send(self) {
+        tokio::spawn(async move {
+            deliver_all(&self.db, self.room_id, self.webhooks, self.event).await;
+        });
+    }
+}
+
+async fn find_webhooks(
+    db: &Database,
+    room_id: ObjectId,
+    event: &str,
+) -> Result<Vec<OutgoingWebhook>, mongodb::error::Error> {
+    let collection: Collection<OutgoingWebhook> = db.collection("outgoing_webhook");
+
+    collection
+        .find(doc! {
+            "room_id": room_id,
+            "disabled": false,
+            "$or": [ { "events": { "$size": 0 } }, { "events": event } ]
+        })
+        .await?
+        .try_collect()
+        .await
+}
+
+async fn deliver_to_room(
+    db: &Database,
+    room_id: ObjectId,
+    event: RoomEvent,
+) -> Result<(), mongodb::error::Error> {
+    let webhooks = find_webhooks(db, room_id, event.name()).await?;
+
+    deliver_all(db, room_id, webhooks, event).await;
+
+    Ok(())
+}
+
+async fn deliver_all(
+    db: &Database,
+    room_id: ObjectId,
+    webhooks: Vec<OutgoingWebhook>,
+    event: RoomEvent,
+) {
+    if webhooks.is_empty() {
+        return;
+    }
+
+    let payload = json!({
+        "event": event.name(),
+        "room_id": room_id.to_hex(),
+        "timestamp": Utc::now().to_rfc3339(),
+        "data": event.data(),
+    })
+    .to_string();
+
+    let deliveries = webhooks
+        .into_iter()
+        .map(|webhook| deliver(db, webhook, event.name(), &payload));
+    futures_util::future::join_all(deliveries).await;
+}
+
+// Posts the payload with exponential backoff and records the outcome in the delivery log
+async fn deliver(db: &Database, webhook: OutgoingWebhook, event: &str, payload: &str) {
+    let collection: Collection<OutgoingWebhook> = db.collection("outgoing_webhook");
+    let delivery_collection: Collection<WebhookDelivery> = db.collection("webhook_delivery");
+
+    let mut delivery = WebhookDelivery {
+        id: ObjectId::new(),
+        webhook_id: webhook.id,
+        event: event.to_string(),
+        payload: payload.to_string(),
+        attempts: 0,
+        status_code: None,
+        error: None,
+        success: false,
+        created_at: DateTime::now(),
+    };
+
+    // Endpoints registered before urls were checked, or whose host moved since, fail here
+    match check_public_url(&webhook.url).await {
+        Ok(()) => post_with_retries(&HTTP_CLIENT, &webhook, event, payload, &mut delivery).await,
+        Err(e) => delivery.error = Some(e),
+    }
+
+    if delivery.success {
+        match collection
+            .update_one(
+                doc! {"_id": webhook.id},
+                doc! { "$set": { "failure_count": 0 } },
+            )
+            .await
+        {
+            // The endpoint was deleted meanwhile, like with its room, so there is no log to keep
+            Ok(result) if result.matched_count == 0 => return,
+            Ok(_) => {}
+            Err(e) => println!("Failed to update the webhook: {e}"),
+        }
+    } else {
+        // Counted on the stored value, concurrent deliveries each see their own count
+        match collection
+            .find_one_and_update(
+                doc! {"_id": webhook.id},
+                doc! { "$inc": { "failure_count": 1 } },
+            )
+            .return_document(ReturnDocument::After)
+            .await
+        {
+            Ok(None) => return,
+            Ok(Some(updated))
+                if updated.failure_count >= MAX_CONSECUTIVE_FAILURES && !updated.disabled =>
+            {
+                println!(
+                    "Disabling outgoing webhook {} after repeated failures",
+                    webhook.id
+                );
+                if let Err(e) = collection
+                    .update_one(
+                        doc! {"_id": webhook.id},
+                        doc! { "$set": { "disabled": true } },
+                    )
+                    .await
+                {
+                    println!("Failed to disable the webhook: {e}");
+                }
+            }
+            Ok(Some(_)) => {}
+            Err(e) => println!("Failed to update the webhook: {e}"),
+        }
+    }
+
+    if let Err(e) = delivery_collection.insert_one(&delivery).await {
+        println!("Failed to record the webhook delivery: {e}");
+    }
+}
+
+// The attempts of one delivery, their outcome is left in the delivery
+async fn post_with_retries(
+    client: &reqwest::Client,
+    webhook: &OutgoingWebhook,
+    event: &str,
+    payload: &str,
+    delivery: &mut WebhookDelivery,
+) {
+    let mut delay = FIRST_RETRY_DELAY;
+    while delivery.attempts < MAX_ATTEMPTS {
+        if delivery.attempts > 0 {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+        delivery.attempts += 1;
+
+        let timestamp = Utc::now().timestamp().to_string();
+        let result = client
+            .post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .header("X-RustChat-Event", event)
+            .header("X-RustChat-Delivery", delivery.id.to_hex())
+            .header("X-RustChat-Timestamp", &timestamp)
+            .header(
+                "X-RustChat-Signature",
+                format!("sha256={}", sign(&webhook.secret, &timestamp, payload)),
+            )
+            .body(payload.to_string())
+            .send()
+            .await;
+
+        match result {
+            Ok(response) => {
+                delivery.status_code = Some(response.status().as_u16());
+                if response.status().is_success() {
+                    delivery.success = true;
+                    delivery.error = None;
+                    break;
+                }
+                delivery.error = Some(format!("The endpoint answered {}", response.status()));
+            }
+            Err(e) => delivery.error = Some(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, extract::State, http::HeaderMap, http::StatusCode, routing::post};
+    use std::sync::Mutex;
+
+    const SECRET: &str = "test-secret";
+
+    // What the receiver got, and how many of the first requests it fails
+    #[derive(Default)]
+    struct Receiver {
+        requests: Mutex<Vec<(HeaderMap, String)>>,
+        failures_left: Mutex<u32>,
+    }
+
+    async fn receive(
+        State(receiver): State<Arc<Receiver>>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        receiver.requests.lock().unwrap().push((headers, body));
+        let mut failures_left = receiver.failures_left.lock().unwrap();
+        if *failures_left > 0 {
+            *failures_left -= 1;
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+        StatusCode::OK
+    }
+
+    // Starts a receiver on a local port, returns it with a webhook pointing at it
+    async fn start_receiver(failures: u32) -> (Arc<Receiver>, OutgoingWebhook) {
+        let receiver = Arc::new(Receiver {
+            failures_left: Mutex::new(failures),
+            ..Default::default()
+        });
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(receiver.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let webhook = OutgoingWebhook {
+            id: ObjectId::new(),
+            room_id: ObjectId::new(),
+            url,
+            secret: SECRET.to_string(),
+            events: Vec::new(),
+            created_by: ObjectId::new(),
+            created_at: DateTime::now(),
+            failure_count: 0,
+            disabled: false,
+        };
+        (receiver, webhook)
+    }
+
+    fn new_delivery(webhook: &OutgoingWebhook) -> WebhookDelivery {
+        WebhookDelivery {
+            id: ObjectId::new(),
+            webhook_id: webhook.id,
+            event: "room.updated".to_string(),
+            payload: String::new(),
+            attempts: 0,
+            status_code: None,
+            error: None,
+            success: false,
+            created_at: DateTime::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_signed() {
+        let (receiver, webhook) = start_receiver(0).await;
+        let payload = r#"{"event":"room.updated"}"#;
+        let mut delivery = new_delivery(&webhook);
+
+        post_with_retries(
+            &reqwest::Client::new(),
+            &webhook,
+            "room.updated",
+            payload,
+            &mut delivery,
+        )
+        .await;
+
+        assert!(delivery.success);
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.status_code, Some(200));
+
+        let requests = receiver.requests.lock().unwrap();
+        let (headers, body) = &requests[0];
+        let header = |name: &str| headers.get(name).unwrap().to_str().unwrap().to_string();
+        assert_eq!(body, payload);
+        assert_eq!(header("X-RustChat-Event"), "room.updated");
+        assert_eq!(header("X-RustChat-Delivery"), delivery.id.to_hex());
+        assert_eq!(
+            header("X-RustChat-Signature"),
+            format!(
+                "sha256={}",
+                sign(SECRET, &header("X-RustChat-Timestamp"), payload)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_attempts_are_retried() {
+        let (receiver, webhook) = start_receiver(2).await;
+        let mut delivery = new_delivery(&webhook);
+
+        post_with_retries(
+            &reqwest::Client::new(),
+            &webhook,
+            "room.updated",
+            "{}",
+            &mut delivery,
+        )
+        .await;
+
+        assert!(delivery.success);
+        assert_eq!(delivery.attempts, 3);
+        assert_eq!(delivery.error, None);
+        assert_eq!(receiver.requests.lock().unwrap().len(), 3);
+    }
+}
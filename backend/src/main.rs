@@ -4,15 +4,20 @@ use mongodb::Database;
 use std::{env, net::SocketAddr, sync::Arc};
 
 // mods
+mod commands;
 mod controller;
+mod events;
 mod middleware;
 mod models;
+mod policy;
 mod routes;
 mod utils;
 
 // crates
+use controller::room_controller::sweep_room_purges;
+use controller::user_controller::resume_account_deletions;
 use routes::router::create_router;
-use utils::db::connect_db;
+use utils::db::{connect_db, create_indexes, migrate_participants};
 
 #[tokio::main]
 async fn main() {
@@ -25,8 +30,22 @@ async fn main() {
 
     println!("The server is up on address: {}", addr);
     let db: Arc<Database> = Arc::new(connect_db().await.expect("Failed to Connect to MongoDb"));
-    let app: Router = create_router(db).await;
+    create_indexes(&db).await.expect("Failed to create the indexes");
+    migrate_participants(&db)
+        .await
+        .expect("Failed to move the room members");
+    let app: Router = create_router(db.clone()).await;
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+
+    // Unfinished deletions are picked up alongside the server instead of holding up startup
+    let jobs_db = db.clone();
+    tokio::spawn(async move {
+        if let Err(e) = resume_account_deletions(&jobs_db).await {
+            println!("Failed to resume the account deletions: {e}");
+        }
+    });
+    tokio::spawn(sweep_room_purges(db));
+
     axum::serve(listener, app).await.unwrap();
 }
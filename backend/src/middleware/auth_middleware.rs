@@ -1,17 +1,46 @@
 use axum::{
-    body::Body, extract::FromRequestParts, http::{Request, StatusCode}, middleware::Next, response::Response, Json
+    body::Body, extract::{FromRequestParts, State}, http::{Request, StatusCode}, middleware::Next, response::Response, Json
 };
-use bson::oid::ObjectId;
+use bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use mongodb::{Collection, Database};
 use serde::{Serialize, Deserialize};
 use serde_json::json;
 use jsonwebtoken::{decode, DecodingKey, Validation};
-use std::env;
+use std::{env, sync::Arc};
+
+use crate::{
+    models::bot_model::{Bot, BotToken, BOT_TOKEN_PREFIX},
+    utils::token::hash_token,
+};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Claims {
     pub user_id: ObjectId,
     pub exp: usize,
     pub iat: usize,
+    // Set when the request was made with a bot API token, never part of a JWT
+    #[serde(skip)]
+    pub bot: Option<BotClaims>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BotClaims {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+impl Claims {
+    // Users can do everything, bots only what their token was scoped to
+    pub fn require_scope(&self, scope: &str) -> Result<(), (StatusCode, String)> {
+        match &self.bot {
+            Some(bot) if !bot.scopes.iter().any(|granted| granted == scope) => Err((
+                StatusCode::FORBIDDEN,
+                format!("The bot token is missing the {scope} scope"),
+            )),
+            _ => Ok(()),
+        }
+    }
 }
 
 pub async fn auth_middleware (
@@ -51,14 +80,75 @@ pub async fn auth_middleware (
             Ok(next.run(request).await)
         } Err(e) => {
             println!("JWT decode error: {}", e);
-            return Err((
+            Err((
                 StatusCode::UNAUTHORIZED,
                 Json(json!({"error": "Invalid token"}))
-            ));
+            ))
         }
     }
 }
 
+// Accepts a user JWT or a bot API token, for the routes bots are allowed on
+pub async fn user_or_bot_middleware (
+    State(db): State<Arc<Database>>,
+    mut request:Request<Body>,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+
+    let bot_token = request
+        .headers()
+        .get("AUTHORIZATION")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .filter(|token| token.starts_with(BOT_TOKEN_PREFIX))
+        .map(|token| token.to_string());
+
+    let Some(bot_token) = bot_token else {
+        return auth_middleware(request, next).await;
+    };
+
+    let token_collection: Collection<BotToken> = db.collection("bot_token");
+    let bot_collection: Collection<Bot> = db.collection("bot");
+
+    let invalid_token = || (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({"error": "Invalid bot token"}))
+    );
+    let db_error = |e: mongodb::error::Error| {
+        println!("Error in finding the bot token: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": "Internal Server Error"}))
+        )
+    };
+
+    let token = token_collection
+        .find_one(doc! { "token_hash": hash_token(&bot_token), "revoked": false })
+        .await
+        .map_err(db_error)?
+        .ok_or_else(invalid_token)?;
+
+    let bot = bot_collection
+        .find_one(doc! { "_id": token.bot_id })
+        .await
+        .map_err(db_error)?
+        .ok_or_else(invalid_token)?;
+
+    let now = Utc::now().timestamp() as usize;
+    request.extensions_mut().insert(Claims {
+        user_id: bot.id,
+        // Bot tokens are long lived and only end when revoked
+        exp: usize::MAX,
+        iat: now,
+        bot: Some(BotClaims {
+            name: bot.name,
+            scopes: token.scopes,
+        }),
+    });
+
+    Ok(next.run(request).await)
+}
+
 // Extrayctor to get the user data from the middleware
 impl<S> FromRequestParts<S> for Claims 
 where S: Send + Sync {
@@ -1,2 +1,3 @@
 pub mod auth_middleware;
-pub mod room_middleware;
\ No newline at end of file
+pub mod room_middleware;
+pub mod presence_middleware;
\ No newline at end of file
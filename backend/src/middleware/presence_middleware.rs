@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use axum::{body::Body, extract::State, http::Request, middleware::Next, response::Response};
+use bson::doc;
+use mongodb::{Collection, Database, bson::DateTime};
+
+use crate::{middleware::auth_middleware::Claims, models::user_model::User};
+
+// A user counts as online while their last request is this recent
+pub const ONLINE_WINDOW_MILLIS: i64 = 5 * 60 * 1000;
+// Skips the write when last_seen_at was refreshed this recently
+const REFRESH_MILLIS: i64 = 60 * 1000;
+
+// Runs after the auth middleware and records when the user was last seen
+pub async fn track_presence(
+    State(db): State<Arc<Database>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    if let Some(claims) = req.extensions().get::<Claims>()
+        && claims.bot.is_none()
+    {
+        let user_id = claims.user_id;
+        tokio::spawn(async move {
+            let collection: Collection<User> = db.collection("user");
+            let now = DateTime::now();
+            let stale = DateTime::from_millis(now.timestamp_millis() - REFRESH_MILLIS);
+
+            if let Err(e) = collection
+                .update_one(
+                    doc! {
+                        "_id": user_id,
+                        "$or": [ { "last_seen_at": null }, { "last_seen_at": { "$lt": stale } } ]
+                    },
+                    doc! { "$set": { "last_seen_at": now } },
+                )
+                .await
+            {
+                println!("Failed to update the presence: {e}");
+            }
+        });
+    }
+
+    next.run(req).await
+}
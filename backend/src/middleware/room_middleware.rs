@@ -1,8 +1,8 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use axum::{
     body::Body,
-    extract::State,
+    extract::{Path, State},
     http::{Request, StatusCode},
     middleware::Next,
     response::Response,
@@ -10,49 +10,167 @@ use axum::{
 use bson::{doc, oid::ObjectId};
 use mongodb::{Collection, Database};
 
-use crate::{middleware::auth_middleware::Claims, models::room_model::Room};
+use crate::{
+    middleware::auth_middleware::Claims,
+    models::room_model::{DeletedRoom, Room},
+    policy,
+};
 
+// Guards the routes with a {room_id} in their path, only members of the room get through.
+// Runs after the auth middleware, which puts the claims in place
 pub async fn in_room(
     State(db): State<Arc<Database>>,
+    Path(params): Path<HashMap<String, String>>,
+    claims: Claims,
     req: Request<Body>,
     next: Next,
 ) -> Result<Response, (StatusCode, String)> {
-    let claims = req
-        .extensions()
-        .get::<Claims>()
-        .ok_or((StatusCode::UNAUTHORIZED, "Missing Claims".to_string()))?
-        .clone();
-
-    let path = req.uri().path();
-    let segments: Vec<&str> = path.split('/').collect();
-    let id = segments.last().unwrap_or(&"").to_string();
+    let room_id = params.get("room_id").ok_or_else(|| {
+        println!("in_room is attached to {} without a room id", req.uri().path());
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal Server Error".to_string(),
+        )
+    })?;
 
-    let room_obj_id = ObjectId::parse_str(&id)
-        .map_err(|_| (StatusCode::NOT_FOUND, "Invalid Room Id".to_string()))?;
-
-    let user_obj_id = claims.user_id;
+    let room_obj_id = ObjectId::parse_str(room_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
 
     let collection: Collection<Room> = db.collection("room");
     let filter = doc! { "_id": room_obj_id };
 
-    match collection.find_one(filter).await {
-        Ok(Some(room)) => {
-            if room.participants.contains(&user_obj_id) {
-                Ok(next.run(req).await)
-            } else {
-                Err((
-                    StatusCode::UNAUTHORIZED,
-                    "You are not part of the given Room".to_string(),
-                ))
-            }
+    let room = match collection.find_one(filter).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return Err(missing_room(&db, room_obj_id).await),
+        Err(e) => {
+            println!("Some error occurred: {e}");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ));
         }
-        Ok(None) => Ok(next.run(req).await),
+    };
+
+    if policy::role_of(&db, &room, claims.user_id).await?.is_none() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You are not part of the given Room".to_string(),
+        ));
+    }
+
+    Ok(next.run(req).await)
+}
+
+// Rooms deleted a short while ago are gone rather than not found, so members still polling
+// them learn why
+async fn missing_room(db: &Database, room_id: ObjectId) -> (StatusCode, String) {
+    let collection: Collection<DeletedRoom> = db.collection("deleted_room");
+
+    match collection.find_one(doc! { "_id": room_id }).await {
+        Ok(Some(_)) => (StatusCode::GONE, "The room was deleted".to_string()),
+        Ok(None) => (StatusCode::NOT_FOUND, "Room not found".to_string()),
         Err(e) => {
             println!("Some error occurred: {e}");
-            Err((
+            (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal Server Error".to_string(),
-            ))
+            )
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, middleware::from_fn_with_state, routing::get};
+    use bson::DateTime;
+    use mongodb::Client;
+    use std::env;
+    use tower::ServiceExt;
+
+    use crate::models::{membership_model::Membership, room_model::RoomRole};
+
+    // A route behind the guard, called as the given user the way the auth middleware leaves it
+    async fn call(db: Arc<Database>, room_id: &str, user_id: ObjectId) -> StatusCode {
+        let app = Router::new()
+            .route("/room/{room_id}", get(|| async { "Hello" }))
+            .route_layer(from_fn_with_state(db.clone(), in_room))
+            .with_state(db);
+        let mut request = Request::builder()
+            .uri(format!("/room/{room_id}"))
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(Claims {
+            user_id,
+            exp: 0,
+            iat: 0,
+            bot: None,
+        });
+
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn an_invalid_room_id_is_refused() {
+        let client = Client::with_uri_str("mongodb://127.0.0.1:1").await.unwrap();
+        let db = Arc::new(client.database("RustChat"));
+
+        let status = call(db, "not-a-room", ObjectId::new()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB in TEST_DB, run with --ignored"]
+    async fn only_members_get_through() {
+        let url = env::var("TEST_DB").expect("TEST_DB is not set");
+        let client = Client::with_uri_str(&url).await.unwrap();
+        let db = Arc::new(client.database(&format!("RustChatTest{}", ObjectId::new())));
+
+        let (room, deleted) = (ObjectId::new(), ObjectId::new());
+        let (owner, member, stranger) = (ObjectId::new(), ObjectId::new(), ObjectId::new());
+        let now = DateTime::now();
+        db.collection::<bson::Document>("room")
+            .insert_one(doc! {
+                "_id": room, "name": "General", "owner": owner, "member_count": 2,
+                "visibility": "public", "created_at": now
+            })
+            .await
+            .unwrap();
+        db.collection::<Membership>("membership")
+            .insert_one(Membership::new(room, member, RoomRole::Member, false))
+            .await
+            .unwrap();
+        db.collection::<DeletedRoom>("deleted_room")
+            .insert_one(DeletedRoom {
+                id: deleted,
+                name: "Old".to_string(),
+                deleted_by: owner,
+                deleted_at: now,
+                members: vec![member],
+                messages_deleted: false,
+            })
+            .await
+            .unwrap();
+
+        let room_id = room.to_hex();
+        let statuses = [
+            call(db.clone(), &ObjectId::new().to_hex(), owner).await,
+            call(db.clone(), &deleted.to_hex(), owner).await,
+            call(db.clone(), &room_id, stranger).await,
+            call(db.clone(), &room_id, member).await,
+            call(db.clone(), &room_id, owner).await,
+        ];
+
+        db.as_ref().drop().await.unwrap();
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::NOT_FOUND,
+                StatusCode::GONE,
+                StatusCode::FORBIDDEN,
+                StatusCode::OK,
+                StatusCode::OK,
+            ]
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+pub const BOT_SCOPES: [&str; 3] = ["messages:read", "messages:write", "rooms:join"];
+
+// Bot tokens carry this prefix so the auth middleware can tell them from JWTs
+pub const BOT_TOKEN_PREFIX: &str = "rcbot_";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bot {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub name: String,
+
+    // The user who created the bot and manages its tokens
+    pub owner: ObjectId,
+
+    pub created_at: DateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotToken {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub bot_id: ObjectId,
+
+    pub token_hash: String,
+
+    pub scopes: Vec<String>,
+
+    pub created_at: DateTime,
+
+    pub revoked: bool,
+}
@@ -0,0 +1,23 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+// A room specific slash command answered by an HTTP endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalCommand {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub room_id: ObjectId,
+
+    // Typed after the slash, stored in lowercase
+    pub name: String,
+
+    pub url: String,
+
+    // Kept in plain text because every invocation is signed with it
+    pub secret: String,
+
+    pub created_by: ObjectId,
+
+    pub created_at: DateTime,
+}
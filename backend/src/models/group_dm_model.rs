@@ -0,0 +1,46 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+pub const MIN_GROUP_DM_MEMBERS: usize = 3;
+pub const MAX_GROUP_DM_MEMBERS: usize = 10;
+
+// A direct conversation between a few people, it has no owner and is never listed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupDm {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    // Sorted, the same people always make the same group
+    pub members: Vec<ObjectId>,
+
+    // The sorted member ids joined, unique so a group is only created once
+    pub member_key: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    pub created_at: DateTime,
+}
+
+impl GroupDm {
+    pub fn new(mut members: Vec<ObjectId>, name: Option<String>) -> Self {
+        members.sort();
+        members.dedup();
+        GroupDm {
+            id: ObjectId::new(),
+            member_key: member_key(&members),
+            members,
+            name,
+            created_at: DateTime::now(),
+        }
+    }
+}
+
+// Expects the members sorted
+pub fn member_key(members: &[ObjectId]) -> String {
+    members
+        .iter()
+        .map(|member| member.to_hex())
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,29 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+// A code that lets people join a private or hidden room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomInvite {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub room_id: ObjectId,
+
+    pub code: String,
+
+    pub created_by: ObjectId,
+
+    pub created_at: DateTime,
+
+    // Never expires when missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime>,
+
+    // Unlimited when missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+
+    pub uses: u32,
+
+    pub revoked: bool,
+}
@@ -0,0 +1,45 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinRequestStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl JoinRequestStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JoinRequestStatus::Pending => "pending",
+            JoinRequestStatus::Approved => "approved",
+            JoinRequestStatus::Rejected => "rejected",
+        }
+    }
+}
+
+// Asks to join a restricted room, an owner or admin decides
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinRequest {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub room_id: ObjectId,
+
+    pub user_id: ObjectId,
+
+    pub status: JoinRequestStatus,
+
+    pub created_at: DateTime,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reviewed_by: Option<ObjectId>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reviewed_at: Option<DateTime>,
+
+    // Shown to the applicant, mostly used when rejecting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
@@ -0,0 +1,70 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+// Crates
+use crate::models::room_model::RoomRole;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationLevel {
+    #[default]
+    All,
+    Mentions,
+    Nothing,
+}
+
+impl NotificationLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NotificationLevel::All => "all",
+            NotificationLevel::Mentions => "mentions",
+            NotificationLevel::Nothing => "nothing",
+        }
+    }
+}
+
+// One user or bot in one room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Membership {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub room_id: ObjectId,
+
+    pub user_id: ObjectId,
+
+    // The owner's entry says owner, but the room's owner field decides
+    pub role: RoomRole,
+
+    pub joined_at: DateTime,
+
+    // Bots never inherit a room
+    #[serde(default)]
+    pub is_bot: bool,
+
+    #[serde(default)]
+    pub notifications: NotificationLevel,
+
+    // The last message the member has seen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_read_message_id: Option<ObjectId>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_read_at: Option<DateTime>,
+}
+
+impl Membership {
+    pub fn new(room_id: ObjectId, user_id: ObjectId, role: RoomRole, is_bot: bool) -> Self {
+        Membership {
+            id: ObjectId::new(),
+            room_id,
+            user_id,
+            role,
+            joined_at: DateTime::now(),
+            is_bot,
+            notifications: NotificationLevel::default(),
+            last_read_message_id: None,
+            last_read_at: None,
+        }
+    }
+}
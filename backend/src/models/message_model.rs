@@ -1,6 +1,8 @@
 use mongodb::bson::{oid::ObjectId, DateTime};
 use serde::{Deserialize, Serialize};
 
+use crate::models::poll_model::Poll;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     #[serde(rename = "_id")]
@@ -14,7 +16,61 @@ pub struct Message {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub room_id: Option<ObjectId>,     
 
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<ObjectId>,
+
     pub content: String,
 
     pub timestamp: DateTime,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded_from: Option<ForwardedFrom>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<ReplySnapshot>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll: Option<Poll>,
+
+    // Set when the message was posted by an integration rather than a user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bot: Option<BotSender>,
+
+    // Set while the message is pinned to the top of its room
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<PinnedBy>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardedFrom {
+    pub message_id: ObjectId,
+
+    // The author of the original message, kept across repeated forwards
+    pub sender_id: ObjectId,
+}
+
+// Copy of the quoted message, so the quote still renders after it is edited or deleted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplySnapshot {
+    pub message_id: ObjectId,
+
+    pub sender_id: ObjectId,
+
+    pub sender_name: String,
+
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotSender {
+    pub bot_id: ObjectId,
+
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedBy {
+    pub user_id: ObjectId,
+
+    pub pinned_at: DateTime,
 }
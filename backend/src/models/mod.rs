@@ -1,3 +1,14 @@
 pub mod user_model;
 pub mod room_model;
-pub mod message_model;
\ No newline at end of file
+pub mod message_model;
+pub mod nonce_model;
+pub mod poll_model;
+pub mod webhook_model;
+pub mod bot_model;
+pub mod command_model;
+pub mod invite_model;
+pub mod join_request_model;
+pub mod moderation_model;
+pub mod membership_model;
+pub mod workspace_model;
+pub mod group_dm_model;
\ No newline at end of file
@@ -0,0 +1,91 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RestrictionKind {
+    // Removed from the room and can't join again
+    Ban,
+    // Stays in the room but can't post
+    Mute,
+}
+
+impl RestrictionKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RestrictionKind::Ban => "ban",
+            RestrictionKind::Mute => "mute",
+        }
+    }
+}
+
+// An active ban or mute, a TTL index removes it once it expires
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomRestriction {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub room_id: ObjectId,
+
+    pub user_id: ObjectId,
+
+    pub kind: RestrictionKind,
+
+    pub actor_id: ObjectId,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    pub created_at: DateTime,
+
+    // Lasts until it is lifted when missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    Kick,
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+}
+
+// Every moderation action is kept here, including the lifted ones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModerationRecord {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub room_id: ObjectId,
+
+    pub user_id: ObjectId,
+
+    pub actor_id: ObjectId,
+
+    pub action: ModerationAction,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime>,
+
+    pub created_at: DateTime,
+}
+
+// When a member last posted in a slow mode room, one per member and room. A TTL index
+// removes it once no interval could still be running
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowModeSlot {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub room_id: ObjectId,
+
+    pub sender_id: ObjectId,
+
+    pub last_posted_at: DateTime,
+}
@@ -0,0 +1,30 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+// Records a client generated nonce so retried sends return the original result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageNonce {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub sender_id: ObjectId,
+
+    pub nonce: String,
+
+    pub message_id: ObjectId,
+
+    // Set for sends into a room, so the record goes with the room
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_id: Option<ObjectId>,
+
+    // Set until the message is stored, a retry meanwhile can't return its id yet.
+    // Records from before the flag existed all had their message stored
+    #[serde(default)]
+    pub pending: bool,
+
+    // The answer of a slash command that only replied to the sender
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply: Option<String>,
+
+    pub created_at: DateTime,
+}
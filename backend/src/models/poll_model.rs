@@ -0,0 +1,47 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Poll {
+    pub question: String,
+
+    pub options: Vec<PollOption>,
+
+    pub multiple_choice: bool,
+
+    // Anonymous polls only return vote counts, never who voted
+    pub anonymous: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<DateTime>,
+
+    pub closed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollOption {
+    pub text: String,
+
+    pub voters: Vec<ObjectId>,
+
+    // Counted from voters by Poll::tally when the poll is returned
+    #[serde(default)]
+    pub votes: usize,
+}
+
+impl Poll {
+    pub fn is_open(&self) -> bool {
+        !self.closed && self.deadline.is_none_or(|deadline| deadline > DateTime::now())
+    }
+
+    // Prepares the poll for a response: counts the votes and hides voters when anonymous
+    pub fn tally(&mut self) {
+        self.closed = !self.is_open();
+        for option in self.options.iter_mut() {
+            option.votes = option.voters.len();
+            if self.anonymous {
+                option.voters.clear();
+            }
+        }
+    }
+}
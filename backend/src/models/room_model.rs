@@ -1,12 +1,143 @@
-use bson::oid::ObjectId;
+use bson::{Bson, DateTime, oid::ObjectId};
 use serde::{Deserialize, Serialize};
 use std::{clone::Clone, fmt::Debug};
 
+// Ordered from the least to the most privileged
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomRole {
+    ReadOnly,
+    Member,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+impl RoomRole {
+    // Same as the serialized name
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RoomRole::ReadOnly => "read_only",
+            RoomRole::Member => "member",
+            RoomRole::Moderator => "moderator",
+            RoomRole::Admin => "admin",
+            RoomRole::Owner => "owner",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomVisibility {
+    // Listed and open to everyone
+    #[default]
+    Public,
+    // Listed, joining creates a request that an owner or admin approves
+    Restricted,
+    // Listed, but only joinable with an invite code
+    Private,
+    // Not listed and only joinable with an invite code
+    Hidden,
+}
+
+impl RoomVisibility {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RoomVisibility::Public => "public",
+            RoomVisibility::Restricted => "restricted",
+            RoomVisibility::Private => "private",
+            RoomVisibility::Hidden => "hidden",
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Room {
     #[serde(rename = "_id")]
     pub id: ObjectId,
     pub name: String,
     pub owner: ObjectId,
-    pub participants: Vec<ObjectId>,
+    // Kept in step with the membership collection
+    #[serde(default)]
+    pub member_count: u32,
+    // Rooms outside of any workspace are open to everyone on the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<ObjectId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    // Bots a member invited, they become members when they join
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invited_bots: Vec<ObjectId>,
+    // Offered the ownership, becomes the owner once they accept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_owner: Option<ObjectId>,
+    // Rooms created before this setting existed are public
+    #[serde(default)]
+    pub visibility: RoomVisibility,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // Url of the room picture
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // Missing on older rooms, their id still holds the creation time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime>,
+    // Time of the last message, the directory sorts on it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_activity_at: Option<DateTime>,
+    // Seconds a member waits between two messages, moderators and up are exempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow_mode_secs: Option<u32>,
+    // Set while the room is archived and read only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_by: Option<ObjectId>,
+}
+
+impl Room {
+    pub fn creation_time(&self) -> DateTime {
+        self.created_at.unwrap_or_else(|| self.id.timestamp())
+    }
+}
+
+// One settings update, with the old and new value of every field it changed
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RoomChange {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub room_id: ObjectId,
+    pub actor_id: ObjectId,
+    pub changes: Vec<FieldChange>,
+    pub created_at: DateTime,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FieldChange {
+    pub field: String,
+    // Null when the field was not set
+    pub old: Bson,
+    pub new: Bson,
+}
+
+// Left behind by a deleted room, so members asking for it are told it is gone. The
+// messages are deleted in batches after the room, messages_deleted is set once they all are
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DeletedRoom {
+    // The id of the room
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    #[serde(default)]
+    pub name: String,
+    pub deleted_by: ObjectId,
+    pub deleted_at: DateTime,
+    // The people who were in the room, each one is taken off once they've seen it
+    #[serde(default)]
+    pub members: Vec<ObjectId>,
+    #[serde(default)]
+    pub messages_deleted: bool,
 }
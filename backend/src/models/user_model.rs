@@ -1,7 +1,11 @@
-use bson::oid::ObjectId;
+use bson::{DateTime, oid::ObjectId};
 use serde::{Deserialize, Serialize};
 use std::{clone::Clone, fmt::Debug};
 
+// Stands in for deleted accounts on the messages they leave behind
+pub const DELETED_USER_ID: ObjectId = ObjectId::from_bytes([0; 12]);
+pub const DELETED_USER_NAME: &str = "Deleted user";
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct User {
     #[serde(rename = "_id")]
@@ -9,4 +13,15 @@ pub struct User {
     pub name: String,
     pub email: String,
     pub password: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    // Refreshed by the presence middleware while the user makes requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen_at: Option<DateTime>,
+    // Server admins can delete other accounts, only set directly in the database
+    #[serde(default)]
+    pub is_admin: bool,
+    // Set when the account deletion starts, the account is gone once it finishes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleting_at: Option<DateTime>,
 }
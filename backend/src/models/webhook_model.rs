@@ -0,0 +1,82 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncomingWebhook {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub room_id: ObjectId,
+
+    // Shown as the sender of the messages it posts
+    pub name: String,
+
+    pub token_hash: String,
+
+    pub created_by: ObjectId,
+
+    pub created_at: DateTime,
+}
+
+// Counts what an incoming webhook posted in one minute, for the rate limit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookRateBucket {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub webhook_id: ObjectId,
+
+    // Start of the minute the bucket counts
+    pub window_start: DateTime,
+
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutgoingWebhook {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub room_id: ObjectId,
+
+    pub url: String,
+
+    // Kept in plain text because every delivery is signed with it
+    pub secret: String,
+
+    // Event names the endpoint subscribed to, empty means all of them
+    pub events: Vec<String>,
+
+    pub created_by: ObjectId,
+
+    pub created_at: DateTime,
+
+    // Deliveries that failed in a row, reset on success
+    pub failure_count: u32,
+
+    pub disabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub webhook_id: ObjectId,
+
+    pub event: String,
+
+    pub payload: String,
+
+    pub attempts: u32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    pub success: bool,
+
+    pub created_at: DateTime,
+}
@@ -0,0 +1,52 @@
+use mongodb::bson::{DateTime, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+// Ordered from the least to the most privileged
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceRole {
+    Member,
+    Admin,
+    Owner,
+}
+
+impl WorkspaceRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WorkspaceRole::Member => "member",
+            WorkspaceRole::Admin => "admin",
+            WorkspaceRole::Owner => "owner",
+        }
+    }
+}
+
+// A team with its own members and rooms
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Workspace {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub name: String,
+
+    pub owner: ObjectId,
+
+    // Rooms every new member joins automatically
+    #[serde(default)]
+    pub default_rooms: Vec<ObjectId>,
+
+    pub created_at: DateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceMember {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+
+    pub workspace_id: ObjectId,
+
+    pub user_id: ObjectId,
+
+    pub role: WorkspaceRole,
+
+    pub joined_at: DateTime,
+}
@@ -0,0 +1,439 @@
+use axum::http::StatusCode;
+use bson::{doc, oid::ObjectId};
+use mongodb::{Collection, Database, bson::DateTime};
+
+// Crates
+use crate::controller::workspace_controller::workspace_role;
+use crate::models::{
+    membership_model::Membership,
+    moderation_model::{RestrictionKind, RoomRestriction, SlowModeSlot},
+    room_model::{Room, RoomRole},
+};
+use crate::utils::db::is_duplicate_key_error;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Permission {
+    ReadMessages,
+    ViewMembers,
+    PostMessages,
+    DeleteOthersMessages,
+    Invite,
+    Kick,
+    Mute,
+    Ban,
+    ReviewJoinRequests,
+    Pin,
+    EditSettings,
+    ManageRoles,
+    TransferOwnership,
+    ArchiveRoom,
+    DeleteRoom,
+}
+
+impl Permission {
+    // The permission matrix, every role at or above this one is allowed
+    fn min_role(self) -> RoomRole {
+        match self {
+            Permission::ReadMessages => RoomRole::ReadOnly,
+            Permission::ViewMembers => RoomRole::ReadOnly,
+            Permission::PostMessages => RoomRole::Member,
+            Permission::DeleteOthersMessages => RoomRole::Moderator,
+            Permission::Invite => RoomRole::Moderator,
+            Permission::Kick => RoomRole::Moderator,
+            Permission::Mute => RoomRole::Moderator,
+            Permission::Ban => RoomRole::Admin,
+            Permission::ReviewJoinRequests => RoomRole::Admin,
+            Permission::Pin => RoomRole::Moderator,
+            Permission::EditSettings => RoomRole::Admin,
+            Permission::ManageRoles => RoomRole::Admin,
+            Permission::TransferOwnership => RoomRole::Owner,
+            Permission::ArchiveRoom => RoomRole::Owner,
+            Permission::DeleteRoom => RoomRole::Owner,
+        }
+    }
+
+    fn action(self) -> &'static str {
+        match self {
+            Permission::ReadMessages => "read this room",
+            Permission::ViewMembers => "see the members of this room",
+            Permission::PostMessages => "post in this room",
+            Permission::DeleteOthersMessages => "delete other members' messages",
+            Permission::Invite => "invite members to this room",
+            Permission::Kick => "remove members from this room",
+            Permission::Mute => "mute members of this room",
+            Permission::Ban => "ban people from this room",
+            Permission::ReviewJoinRequests => "review the join requests of this room",
+            Permission::Pin => "pin messages in this room",
+            Permission::EditSettings => "change the settings of this room",
+            Permission::ManageRoles => "change member roles in this room",
+            Permission::TransferOwnership => "hand this room over",
+            Permission::ArchiveRoom => "archive this room",
+            Permission::DeleteRoom => "delete this room",
+        }
+    }
+}
+
+impl RoomRole {
+    pub fn can(self, permission: Permission) -> bool {
+        self >= permission.min_role()
+    }
+}
+
+// None when the user is not part of the room
+pub async fn role_of(
+    db: &Database,
+    room: &Room,
+    user_id: ObjectId,
+) -> Result<Option<RoomRole>, (StatusCode, String)> {
+    if user_id == room.owner {
+        return Ok(Some(RoomRole::Owner));
+    }
+
+    Ok(find_membership(db, room.id, user_id)
+        .await?
+        .map(|membership| membership.role))
+}
+
+pub async fn find_membership(
+    db: &Database,
+    room_id: ObjectId,
+    user_id: ObjectId,
+) -> Result<Option<Membership>, (StatusCode, String)> {
+    let collection: Collection<Membership> = db.collection("membership");
+
+    collection
+        .find_one(doc! {"room_id": room_id, "user_id": user_id})
+        .await
+        .map_err(internal_error)
+}
+
+pub async fn is_member(
+    db: &Database,
+    room_id: ObjectId,
+    user_id: ObjectId,
+) -> Result<bool, (StatusCode, String)> {
+    Ok(find_membership(db, room_id, user_id).await?.is_some())
+}
+
+// The single check every room and message handler goes through, returns the caller's role
+pub async fn check(
+    db: &Database,
+    room: &Room,
+    user_id: ObjectId,
+    permission: Permission,
+) -> Result<RoomRole, (StatusCode, String)> {
+    let role = role_of(db, room, user_id).await?.ok_or((
+        StatusCode::FORBIDDEN,
+        "You are not part of the given Room".to_string(),
+    ))?;
+
+    if !role.can(permission) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("You don't have permission to {}", permission.action()),
+        ));
+    }
+
+    if let Permission::PostMessages | Permission::Pin = permission {
+        check_not_archived(room)?;
+    }
+
+    if room.archived_at.is_some() {
+        check_archived_access(db, room, user_id).await?;
+    }
+
+    Ok(role)
+}
+
+// Archived rooms keep their members as they were, so people who left the room's workspace
+// since are kept out here. Owners keep their rooms when they leave, and bots are never
+// workspace members
+async fn check_archived_access(
+    db: &Database,
+    room: &Room,
+    user_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+    if room.workspace_id.is_none() || user_id == room.owner {
+        return Ok(());
+    }
+
+    if find_membership(db, room.id, user_id)
+        .await?
+        .is_some_and(|membership| membership.is_bot)
+    {
+        return Ok(());
+    }
+
+    check_in_workspace(db, room, user_id).await
+}
+
+// Actions on another member, like kicking or changing their role, need a higher role than theirs
+pub async fn check_outranks(
+    db: &Database,
+    room: &Room,
+    actor_id: ObjectId,
+    target_id: ObjectId,
+    permission: Permission,
+) -> Result<RoomRole, (StatusCode, String)> {
+    let role = check(db, room, actor_id, permission).await?;
+
+    if let Some(target_role) = role_of(db, room, target_id).await?
+        && target_role >= role
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "You can only act on members below your role".to_string(),
+        ));
+    }
+
+    Ok(role)
+}
+
+// Loads the room and checks the caller's permission in one go
+pub async fn find_room(
+    db: &Database,
+    room_id: &str,
+    user_id: ObjectId,
+    permission: Permission,
+) -> Result<Room, (StatusCode, String)> {
+    let room_collection: Collection<Room> = db.collection("room");
+
+    let room_obj_id = ObjectId::parse_str(room_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Room Id".to_string()))?;
+
+    let room = match room_collection.find_one(doc! {"_id": room_obj_id}).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "Room not found".to_string())),
+        Err(e) => {
+            println!("Error in finding the room: {e}");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal Server Error".to_string(),
+            ));
+        }
+    };
+
+    check(db, &room, user_id, permission).await?;
+
+    Ok(room)
+}
+
+// The check for joining a room, which by definition the user is not part of yet. Invite
+// codes and approved requests skip the visibility, the rest applies to every way in
+pub async fn check_can_join(
+    db: &Database,
+    room: &Room,
+    user_id: ObjectId,
+    is_bot: bool,
+) -> Result<(), (StatusCode, String)> {
+    if role_of(db, room, user_id).await?.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "User is already in the group".to_string(),
+        ));
+    }
+
+    check_not_archived(room)?;
+    check_not_restricted(db, room.id, user_id, RestrictionKind::Ban).await?;
+
+    // Invited bots are let into workspace rooms, their owner was checked on the invite
+    if !is_bot {
+        check_in_workspace(db, room, user_id).await?;
+    }
+
+    // Bots can only join the rooms they were invited to
+    if is_bot && !room.invited_bots.contains(&user_id) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "The bot was not invited to this room".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Rooms of a workspace are closed to people outside of it, whichever way they come in
+pub async fn check_in_workspace(
+    db: &Database,
+    room: &Room,
+    user_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+    if let Some(workspace_id) = room.workspace_id
+        && workspace_role(db, workspace_id, user_id).await?.is_none()
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "This room belongs to a workspace you are not part of".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Any member but the owner can leave, the owner hands the room over first
+pub async fn check_can_leave(
+    db: &Database,
+    room: &Room,
+    user_id: ObjectId,
+) -> Result<(), (StatusCode, String)> {
+    check_not_archived(room)?;
+
+    if room.owner == user_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You are the owner of the room, transfer it before leaving".to_string(),
+        ));
+    }
+
+    if role_of(db, room, user_id).await?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "The user was never a part of the room".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Archived rooms stay readable, but nothing is posted and nobody joins or leaves
+pub fn check_not_archived(room: &Room) -> Result<(), (StatusCode, String)> {
+    if room.archived_at.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "This room is archived and read only".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Rejects banned users joining and muted members posting
+pub async fn check_not_restricted(
+    db: &Database,
+    room_id: ObjectId,
+    user_id: ObjectId,
+    kind: RestrictionKind,
+) -> Result<(), (StatusCode, String)> {
+    let collection: Collection<RoomRestriction> = db.collection("room_restriction");
+
+    // The TTL monitor only runs every minute, so expired entries are filtered here too
+    let restriction = collection
+        .find_one(doc! {
+            "room_id": room_id,
+            "user_id": user_id,
+            "kind": kind.as_str(),
+            "$or": [ { "expires_at": null }, { "expires_at": { "$gt": DateTime::now() } } ]
+        })
+        .await
+        .map_err(internal_error)?;
+
+    let Some(restriction) = restriction else {
+        return Ok(());
+    };
+
+    let what = match kind {
+        RestrictionKind::Ban => "banned from",
+        RestrictionKind::Mute => "muted in",
+    };
+    let until = restriction
+        .expires_at
+        .and_then(|expires_at| expires_at.try_to_rfc3339_string().ok())
+        .map(|expires_at| format!(" until {expires_at}"))
+        .unwrap_or_default();
+
+    Err((
+        StatusCode::FORBIDDEN,
+        format!("You are {what} this room{until}"),
+    ))
+}
+
+// Longest interval a room can set
+pub const MAX_SLOW_MODE_SECS: u32 = 6 * 60 * 60;
+
+pub enum SlowMode {
+    // Slow mode is off, or the member is above it
+    Exempt,
+    // The member took their turn, given back with release_slow_mode if the post fails
+    Taken(DateTime),
+    // Seconds the member still has to wait, rounded up so retrying then goes through
+    Wait(u64),
+}
+
+// Takes the member's turn to post. The slot only moves forward once the interval is
+// over, and the unique index on (room_id, sender_id) turns a concurrent second post into
+// a duplicate key, so two posts can't both get through
+pub async fn take_slow_mode(
+    db: &Database,
+    room: &Room,
+    user_id: ObjectId,
+    role: RoomRole,
+) -> Result<SlowMode, (StatusCode, String)> {
+    let collection: Collection<SlowModeSlot> = db.collection("slow_mode_slot");
+
+    let Some(interval) = room.slow_mode_secs.filter(|secs| *secs > 0) else {
+        return Ok(SlowMode::Exempt);
+    };
+
+    if role >= RoomRole::Moderator {
+        return Ok(SlowMode::Exempt);
+    }
+
+    let interval_millis = i64::from(interval) * 1000;
+    let now = DateTime::now();
+    let cutoff = DateTime::from_millis(now.timestamp_millis() - interval_millis);
+
+    let result = collection
+        .update_one(
+            doc! {
+                "room_id": room.id,
+                "sender_id": user_id,
+                "last_posted_at": { "$lte": cutoff }
+            },
+            doc! { "$set": { "last_posted_at": now } },
+        )
+        .upsert(true)
+        .await;
+
+    match result {
+        Ok(_) => return Ok(SlowMode::Taken(now)),
+        Err(e) if is_duplicate_key_error(&e) => {}
+        Err(e) => return Err(internal_error(e)),
+    }
+
+    let last = collection
+        .find_one(doc! {"room_id": room.id, "sender_id": user_id})
+        .await
+        .map_err(internal_error)?
+        .map(|slot| slot.last_posted_at.timestamp_millis())
+        .unwrap_or(now.timestamp_millis());
+
+    let wait_millis = last + interval_millis - now.timestamp_millis();
+    Ok(SlowMode::Wait(
+        (wait_millis.max(0) as u64).div_ceil(1000).max(1),
+    ))
+}
+
+// Gives the turn back after a post that didn't go through
+pub async fn release_slow_mode(
+    db: &Database,
+    room_id: ObjectId,
+    user_id: ObjectId,
+    taken_at: DateTime,
+) {
+    let collection: Collection<SlowModeSlot> = db.collection("slow_mode_slot");
+
+    if let Err(e) = collection
+        .delete_one(doc! {"room_id": room_id, "sender_id": user_id, "last_posted_at": taken_at})
+        .await
+    {
+        println!("Failed to release the slow mode slot: {e}");
+    }
+}
+
+fn internal_error(e: mongodb::error::Error) -> (StatusCode, String) {
+    println!("Some error occurred: {e}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal Server Error".to_string(),
+    )
+}
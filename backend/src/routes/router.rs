@@ -1,15 +1,24 @@
 use axum::{
-    http::{HeaderValue, Method}, middleware::{from_fn, from_fn_with_state}, routing::{delete, get, post, put}, Router
+    Router,
+    http::{HeaderValue, Method},
+    middleware::{from_fn, from_fn_with_state},
+    routing::{delete, get, patch, post, put},
 };
 use mongodb::Database;
 use std::sync::Arc;
-use tower_http::cors::{CorsLayer, Any};
+use tower_http::cors::{Any, CorsLayer};
 
 use crate::{
     controller::{
-        auth_controller::*, message_controller::*, room_controller::*, user_controller::*,
+        auth_controller::*, bot_controller::*, command_controller::*, directory_controller::*,
+        group_dm_controller::*, invite_controller::*, join_request_controller::*,
+        member_controller::*, message_controller::*, moderation_controller::*, poll_controller::*,
+        room_controller::*, room_settings_controller::*, user_controller::*, webhook_controller::*,
+        workspace_controller::*,
+    },
+    middleware::{
+        auth_middleware::*, presence_middleware::track_presence, room_middleware::in_room,
     },
-    middleware::{auth_middleware::*, room_middleware::*},
 };
 
 pub async fn create_router(db: Arc<Database>) -> Router {
@@ -17,42 +26,236 @@ pub async fn create_router(db: Arc<Database>) -> Router {
         .route("/", get(|| async { "Trail Router" }))
         .route("/api/auth/register", post(register))
         .route("/api/auth/login", post(login))
-        .route("/api/user/getUser/{id}", get(get_user_by_id))
-        .route("/api/user/getAll", get(get_all_user))
-        .route("/api/user/search/{name}", get(search_by_name))
-        .route("/api/room/getAll", get(get_all_rooms));
+        .route("/api/hooks/{id}/{token}", post(post_webhook_message));
 
     let protected_routes = Router::new()
         .route("/api/user/delete/{id}", delete(delete_user))
+        .route("/api/user/getUser/{id}", get(get_user_by_id))
+        .route("/api/user/getAll", get(get_all_user))
+        .route("/api/user/search/{name}", get(search_by_name))
         .route("/api/room/create", post(create_room))
-        .route("/api/room/{id}", get(get_room))
-        .route("/api/room/join/{room_id}", put(join_room))
-        .route("/api/room/leave/{id}", put(leave_room))
-        .route("/api/room/delete/{id}", delete(delete_room))
-        .route("/api/message/delete/{id}", delete(delete_message_in_room))
+        .route("/api/room/directory", get(get_room_directory))
+        .route("/api/room/getAll", get(get_all_rooms))
+        .route("/api/room/invite/revoke/{invite_id}", delete(revoke_invite))
+        .route("/api/room/join/invite/{code}", put(join_with_invite))
+        .route("/api/room/requests/mine", get(get_my_join_requests))
+        .route("/api/room/deleted", get(get_deleted_rooms))
+        .route("/api/room/deleted/{id}", delete(dismiss_deleted_room))
         .route("/api/message/deleteDM/{id}", delete(delete_message_in_dm))
         .route("/api/message/{current_user_id}", get(get_users_with_recent_chats))
         .route("/api/messages/{user1_id}/{user2_id}", get(get_messages_between_users))
-        .route("/api/message/room/{room_id}", get(get_messages_in_room))
+        .route("/api/webhook/delete/{id}", delete(delete_webhook))
+        .route("/api/webhook/outgoing/deliveries/{id}", get(get_webhook_deliveries))
+        .route("/api/webhook/outgoing/enable/{id}", put(enable_outgoing_webhook))
+        .route("/api/webhook/outgoing/delete/{id}", delete(delete_outgoing_webhook))
+        .route("/api/bot/create", post(create_bot))
+        .route("/api/bot/mine", get(get_my_bots))
+        .route("/api/bot/token/{id}", post(create_bot_token))
+        .route("/api/bot/token/{id}", delete(revoke_bot_token))
+        .route("/api/bot/delete/{id}", delete(delete_bot))
+        .route("/api/command/delete/{id}", delete(delete_command))
+        .route("/api/workspace/create", post(create_workspace))
+        .route("/api/workspace/mine", get(get_my_workspaces))
+        .route("/api/workspace/members/{id}", get(get_workspace_members))
+        .route("/api/workspace/member/{id}/{user_id}", put(add_workspace_member))
+        .route("/api/workspace/member/{id}/{user_id}", delete(remove_workspace_member))
+        .route("/api/workspace/role/{id}/{user_id}", put(set_workspace_role))
+        .route("/api/workspace/defaults/{id}", put(set_default_rooms))
+        .route("/api/group/open", post(open_group_dm))
+        .route("/api/group/mine", get(get_my_group_dms))
+        .route("/api/group/messages/{id}", get(get_group_messages))
+        .layer(from_fn_with_state(db.clone(), track_presence))
         .layer(from_fn(auth_middleware));
 
+    // Routes on a room that only its members can call, every one of them has a {room_id}
+    let room_routes = Router::new()
+        .route("/api/room/delete/{room_id}", delete(delete_room))
+        .route("/api/room/role/{room_id}/{user_id}", put(set_member_role))
+        .route("/api/room/transfer/{room_id}", put(transfer_ownership))
+        .route("/api/room/transfer/accept/{room_id}", put(accept_ownership))
+        .route("/api/room/transfer/decline/{room_id}", put(decline_ownership))
+        .route("/api/room/{room_id}", patch(update_room_settings))
+        .route("/api/room/visibility/{room_id}", put(set_room_visibility))
+        .route("/api/room/archive/{room_id}", put(archive_room))
+        .route("/api/room/unarchive/{room_id}", put(unarchive_room))
+        .route("/api/room/changes/{room_id}", get(get_room_changes))
+        .route("/api/room/members/{room_id}", get(get_room_members))
+        .route("/api/room/membership/{room_id}", patch(update_membership))
+        .route("/api/room/invite/{room_id}", post(create_invite))
+        .route("/api/room/invites/{room_id}", get(get_room_invites))
+        .route("/api/room/requests/{room_id}", get(get_room_join_requests))
+        .route("/api/room/requests/approve/{room_id}/{id}", put(approve_join_request))
+        .route("/api/room/requests/reject/{room_id}/{id}", put(reject_join_request))
+        .route("/api/room/kick/{room_id}/{user_id}", put(kick_user))
+        .route("/api/room/ban/{room_id}/{user_id}", put(ban_user))
+        .route("/api/room/ban/{room_id}/{user_id}", delete(unban_user))
+        .route("/api/room/mute/{room_id}/{user_id}", put(mute_user))
+        .route("/api/room/mute/{room_id}/{user_id}", delete(unmute_user))
+        .route("/api/room/bans/{room_id}", get(get_room_bans))
+        .route("/api/room/mutes/{room_id}", get(get_room_mutes))
+        .route("/api/room/moderation/{room_id}", get(get_moderation_log))
+        .route("/api/webhook/create/{room_id}", post(create_webhook))
+        .route("/api/webhook/room/{room_id}", get(get_room_webhooks))
+        .route("/api/webhook/outgoing/create/{room_id}", post(create_outgoing_webhook))
+        .route("/api/webhook/outgoing/room/{room_id}", get(get_outgoing_webhooks))
+        .route("/api/bot/invite/{room_id}/{bot_id}", put(invite_bot))
+        .route("/api/command/create/{room_id}", post(create_command))
+        .route("/api/command/room/{room_id}", get(get_room_commands))
+        .route("/api/message/pin/{room_id}/{message_id}", put(pin_message))
+        .route("/api/message/pin/{room_id}/{message_id}", delete(unpin_message))
+        .route("/api/message/pins/{room_id}", get(get_pinned_messages))
+        .route("/api/message/delete/{room_id}/{id}", delete(delete_message_in_room))
+        .route("/api/message/poll/close/{room_id}/{id}", put(close_poll))
+        .route_layer(from_fn_with_state(db.clone(), in_room))
+        .layer(from_fn_with_state(db.clone(), track_presence))
+        .layer(from_fn(auth_middleware));
 
     let message_routes = Router::new()
-        .route("/api/message/send/{id}", post(send_message))
-        .route("/api/messages/getRoomMessages/{id}", get(get_messages_by_room_id))
         .route("/api/messages/getDM/{id}", get(get_messages_in_dm))
+        .layer(from_fn_with_state(db.clone(), track_presence))
         .layer(from_fn(auth_middleware));
 
+    // Routes that bots can call with an API token, as well as users with a JWT.
+    // send_message takes a room, group or user id, for rooms it checks the membership itself,
+    // /api/message/room/{room_id} is the same send behind in_room
+    let bot_routes = Router::new()
+        .route("/api/room/join/{id}", put(join_room))
+        .route("/api/message/send/{id}", post(send_message))
+        .route("/api/message/forward/{id}", post(forward_message))
+        .layer(from_fn_with_state(db.clone(), track_presence))
+        .layer(from_fn_with_state(db.clone(), user_or_bot_middleware));
+
+    let bot_room_routes = Router::new()
+        .route("/api/room/{room_id}", get(get_room))
+        .route("/api/room/leave/{room_id}", put(leave_room))
+        .route("/api/message/room/{room_id}", get(get_messages_in_room))
+        .route("/api/message/room/{room_id}", post(send_message))
+        .route("/api/message/poll/{room_id}", post(create_poll))
+        .route("/api/message/poll/vote/{room_id}/{id}", put(vote_poll))
+        .route("/api/messages/getRoomMessages/{room_id}", get(get_messages_by_room_id))
+        .route_layer(from_fn_with_state(db.clone(), in_room))
+        .layer(from_fn_with_state(db.clone(), track_presence))
+        .layer(from_fn_with_state(db.clone(), user_or_bot_middleware));
+
     let origin = HeaderValue::from_str("http://localhost:5173").expect("Invalid header Value");
 
     let cors = CorsLayer::new()
         .allow_origin(origin)
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
         .allow_headers(Any);
 
     public_routes
         .merge(protected_routes)
+        .merge(room_routes)
         .merge(message_routes)
+        .merge(bot_routes)
+        .merge(bot_room_routes)
         .with_state(db)
         .layer(cors)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, http::StatusCode};
+    use bson::oid::ObjectId;
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use mongodb::Client;
+    use std::env;
+    use tower::ServiceExt;
+
+    // Every route that acts on a single room, except /api/message/send which checks the room itself
+    const ROOM_ROUTES: &[(&str, &str)] = &[
+        ("DELETE", "/api/room/delete/{room_id}"),
+        ("PUT", "/api/room/role/{room_id}/{id}"),
+        ("PUT", "/api/room/transfer/{room_id}"),
+        ("PUT", "/api/room/transfer/accept/{room_id}"),
+        ("PUT", "/api/room/transfer/decline/{room_id}"),
+        ("PATCH", "/api/room/{room_id}"),
+        ("PUT", "/api/room/visibility/{room_id}"),
+        ("PUT", "/api/room/archive/{room_id}"),
+        ("PUT", "/api/room/unarchive/{room_id}"),
+        ("GET", "/api/room/changes/{room_id}"),
+        ("GET", "/api/room/members/{room_id}"),
+        ("PATCH", "/api/room/membership/{room_id}"),
+        ("POST", "/api/room/invite/{room_id}"),
+        ("GET", "/api/room/invites/{room_id}"),
+        ("GET", "/api/room/requests/{room_id}"),
+        ("PUT", "/api/room/requests/approve/{room_id}/{id}"),
+        ("PUT", "/api/room/requests/reject/{room_id}/{id}"),
+        ("PUT", "/api/room/kick/{room_id}/{id}"),
+        ("PUT", "/api/room/ban/{room_id}/{id}"),
+        ("DELETE", "/api/room/ban/{room_id}/{id}"),
+        ("PUT", "/api/room/mute/{room_id}/{id}"),
+        ("DELETE", "/api/room/mute/{room_id}/{id}"),
+        ("GET", "/api/room/bans/{room_id}"),
+        ("GET", "/api/room/mutes/{room_id}"),
+        ("GET", "/api/room/moderation/{room_id}"),
+        ("POST", "/api/webhook/create/{room_id}"),
+        ("GET", "/api/webhook/room/{room_id}"),
+        ("POST", "/api/webhook/outgoing/create/{room_id}"),
+        ("GET", "/api/webhook/outgoing/room/{room_id}"),
+        ("PUT", "/api/bot/invite/{room_id}/{id}"),
+        ("POST", "/api/command/create/{room_id}"),
+        ("GET", "/api/command/room/{room_id}"),
+        ("PUT", "/api/message/pin/{room_id}/{id}"),
+        ("DELETE", "/api/message/pin/{room_id}/{id}"),
+        ("GET", "/api/message/pins/{room_id}"),
+        ("DELETE", "/api/message/delete/{room_id}/{id}"),
+        ("PUT", "/api/message/poll/close/{room_id}/{id}"),
+        ("GET", "/api/room/{room_id}"),
+        ("PUT", "/api/room/leave/{room_id}"),
+        ("GET", "/api/message/room/{room_id}"),
+        ("POST", "/api/message/room/{room_id}"),
+        ("POST", "/api/message/poll/{room_id}"),
+        ("PUT", "/api/message/poll/vote/{room_id}/{id}"),
+        ("GET", "/api/messages/getRoomMessages/{room_id}"),
+    ];
+
+    // Signs a JWT the way login does, with the secret the auth middleware reads
+    fn token(user_id: ObjectId) -> String {
+        if env::var("JWT_SECRET").is_err() {
+            // SAFETY: the only test in this crate that touches the environment
+            unsafe { env::set_var("JWT_SECRET", "test-secret") };
+        }
+        let now = chrono::Utc::now().timestamp() as usize;
+        let claims = Claims {
+            user_id,
+            exp: now + 60 * 60,
+            iat: now,
+            bot: None,
+        };
+        let secret = env::var("JWT_SECRET").unwrap();
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_ref()),
+        )
+        .unwrap()
+    }
+
+    // The guard parses the room id before the handler runs, so an invalid one is refused
+    // without a database. Who gets through is tested on in_room itself
+    #[tokio::test]
+    async fn room_routes_are_behind_the_room_guard() {
+        let client = Client::with_uri_str("mongodb://127.0.0.1:1").await.unwrap();
+        let app = create_router(Arc::new(client.database("RustChat"))).await;
+        let token = token(ObjectId::new());
+
+        for (method, path) in ROOM_ROUTES {
+            let uri = path
+                .replace("{room_id}", "not-a-room")
+                .replace("{id}", &ObjectId::new().to_hex());
+            let request = Request::builder()
+                .method(*method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {token}"))
+                .header("Content-Type", "application/json")
+                .body(Body::from("{}"))
+                .unwrap();
+
+            let status = app.clone().oneshot(request).await.unwrap().status();
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{method} {path}");
+        }
+    }
+}
@@ -1,5 +1,35 @@
-use mongodb::{Database, options::ClientOptions, Client};
-use std::env;
+use futures_util::TryStreamExt;
+use mongodb::{
+    Client, Collection, Database, IndexModel,
+    bson::{Bson, DateTime, doc, oid::ObjectId},
+    error::{Error, ErrorKind, WriteFailure},
+    options::{ClientOptions, IndexOptions},
+};
+use serde::Deserialize;
+use std::{collections::HashMap, env, time::Duration};
+
+use crate::models::{
+    bot_model::{Bot, BotToken},
+    command_model::ExternalCommand,
+    group_dm_model::GroupDm,
+    invite_model::RoomInvite,
+    join_request_model::JoinRequest,
+    membership_model::Membership,
+    message_model::Message,
+    moderation_model::{ModerationRecord, RoomRestriction, SlowModeSlot},
+    nonce_model::MessageNonce,
+    room_model::{DeletedRoom, RoomChange, RoomRole},
+    webhook_model::{WebhookDelivery, WebhookRateBucket},
+    workspace_model::WorkspaceMember,
+};
+use crate::policy::MAX_SLOW_MODE_SECS;
+
+// How long a client nonce keeps deduplicating retried sends
+pub const NONCE_WINDOW_SECS: u64 = 24 * 60 * 60;
+// How long the outcome of a webhook delivery stays in the delivery log
+pub const DELIVERY_LOG_SECS: u64 = 7 * 24 * 60 * 60;
+// How long members asking for a deleted room are told it was deleted
+pub const DELETED_ROOM_SECS: u64 = 30 * 24 * 60 * 60;
 
 pub async fn connect_db() -> Result<Database, mongodb::error::Error> {
     let url = env::var("db").expect("MongoDB URL is not set in the environment variables");
@@ -9,3 +39,394 @@ pub async fn connect_db() -> Result<Database, mongodb::error::Error> {
 
     Ok(client.database("RustChat"))
 }
+
+pub async fn create_indexes(db: &Database) -> Result<(), mongodb::error::Error> {
+    let nonce_collection: Collection<MessageNonce> = db.collection("message_nonce");
+
+    // One nonce per sender, so concurrent retries can't both insert a message
+    nonce_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "sender_id": 1, "nonce": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+
+    // The nonces of a room, removed with it
+    nonce_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "room_id": 1 })
+                .options(IndexOptions::builder().sparse(true).build())
+                .build(),
+        )
+        .await?;
+
+    // Expire nonces once the retry window is over
+    nonce_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "created_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(NONCE_WINDOW_SECS))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+
+    // One rate bucket per webhook and minute
+    let bucket_collection: Collection<WebhookRateBucket> = db.collection("webhook_rate_bucket");
+    bucket_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "webhook_id": 1, "window_start": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+
+    // Buckets are only read during their own minute
+    bucket_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "window_start": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(5 * 60))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+
+    // One slow mode slot per member and room, so only one post can take it
+    let slot_collection: Collection<SlowModeSlot> = db.collection("slow_mode_slot");
+    slot_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "room_id": 1, "sender_id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+
+    // No interval runs longer than the longest slow mode
+    slot_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "last_posted_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(u64::from(MAX_SLOW_MODE_SECS)))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+
+    let message_collection: Collection<Message> = db.collection("message");
+
+    // The history of a room, also read in batches when the room is deleted
+    message_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "room_id": 1, "timestamp": 1 })
+                .options(IndexOptions::builder().sparse(true).build())
+                .build(),
+        )
+        .await?;
+
+    // The history of a group DM
+    message_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "group_id": 1, "timestamp": 1 })
+                .options(IndexOptions::builder().sparse(true).build())
+                .build(),
+        )
+        .await?;
+
+    // Bot tokens are looked up by their hash on every request
+    let bot_token_collection: Collection<BotToken> = db.collection("bot_token");
+    bot_token_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "token_hash": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+
+    // A command name can only be registered once per room
+    let command_collection: Collection<ExternalCommand> = db.collection("external_command");
+    command_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "room_id": 1, "name": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+
+    // Invites are looked up by their code when someone joins
+    let invite_collection: Collection<RoomInvite> = db.collection("room_invite");
+    invite_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "code": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+
+    // One pending request per user and room, reviewed ones are kept as history
+    let join_request_collection: Collection<JoinRequest> = db.collection("join_request");
+    join_request_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "room_id": 1, "user_id": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! { "status": "pending" })
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+
+    // One active ban and one active mute per user and room
+    let restriction_collection: Collection<RoomRestriction> = db.collection("room_restriction");
+    restriction_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "room_id": 1, "user_id": 1, "kind": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+
+    // Lift timed bans and mutes once they expire, permanent ones have no expires_at
+    restriction_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(0))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+
+    let moderation_collection: Collection<ModerationRecord> = db.collection("moderation_log");
+    moderation_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "room_id": 1, "created_at": -1 })
+                .build(),
+        )
+        .await?;
+
+    let change_collection: Collection<RoomChange> = db.collection("room_change");
+    change_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "room_id": 1, "created_at": -1 })
+                .build(),
+        )
+        .await?;
+
+    // Deleted rooms are only remembered for a while
+    let deleted_room_collection: Collection<DeletedRoom> = db.collection("deleted_room");
+    deleted_room_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "deleted_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(DELETED_ROOM_SECS))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+    deleted_room_collection
+        .create_index(IndexModel::builder().keys(doc! { "members": 1 }).build())
+        .await?;
+
+    // One membership per user and room
+    let membership_collection: Collection<Membership> = db.collection("membership");
+    membership_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "room_id": 1, "user_id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+
+    // The rooms of a user, for the directory and account deletion
+    membership_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "user_id": 1, "joined_at": 1 })
+                .build(),
+        )
+        .await?;
+
+    // Picking the successor of an owner goes by join time
+    membership_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "room_id": 1, "joined_at": 1 })
+                .build(),
+        )
+        .await?;
+
+    // One membership per user and workspace
+    let workspace_member_collection: Collection<WorkspaceMember> =
+        db.collection("workspace_member");
+    workspace_member_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "workspace_id": 1, "user_id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+
+    // The workspaces of a user, for scoping rooms and searches
+    workspace_member_collection
+        .create_index(IndexModel::builder().keys(doc! { "user_id": 1 }).build())
+        .await?;
+
+    // The same people only ever share one group DM
+    let group_dm_collection: Collection<GroupDm> = db.collection("group_dm");
+    group_dm_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "member_key": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        )
+        .await?;
+
+    // The group DMs of a user
+    group_dm_collection
+        .create_index(IndexModel::builder().keys(doc! { "members": 1 }).build())
+        .await?;
+
+    // The delivery log of an endpoint, newest first
+    let delivery_collection: Collection<WebhookDelivery> = db.collection("webhook_delivery");
+    delivery_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "webhook_id": 1, "created_at": -1 })
+                .build(),
+        )
+        .await?;
+
+    // Old deliveries expire, so the log doesn't grow with every event
+    delivery_collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "created_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Duration::from_secs(DELIVERY_LOG_SECS))
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+
+    println!("Indexes are in place");
+    Ok(())
+}
+
+// A room as it was stored before members had their own collection
+#[derive(Deserialize)]
+struct LegacyRoom {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    owner: ObjectId,
+    participants: Vec<ObjectId>,
+    #[serde(default)]
+    roles: HashMap<String, RoomRole>,
+    created_at: Option<DateTime>,
+}
+
+// Moves the embedded participants arrays into the membership collection. A room is only
+// unset once all its members are copied, so an interrupted run picks up where it stopped
+pub async fn migrate_participants(db: &Database) -> Result<(), mongodb::error::Error> {
+    let room_collection: Collection<LegacyRoom> = db.collection("room");
+    let membership_collection: Collection<Membership> = db.collection("membership");
+    let bot_collection: Collection<Bot> = db.collection("bot");
+
+    let rooms: Vec<LegacyRoom> = room_collection
+        .find(doc! { "participants": { "$exists": true } })
+        .await?
+        .try_collect()
+        .await?;
+
+    for room in &rooms {
+        let bots = bot_collection
+            .distinct("_id", doc! { "_id": { "$in": &room.participants } })
+            .await?;
+
+        // Join times were never stored, the room's creation is the best guess
+        let joined_at = room.created_at.unwrap_or_else(|| room.id.timestamp());
+
+        for user_id in &room.participants {
+            let role = if *user_id == room.owner {
+                RoomRole::Owner
+            } else {
+                room.roles
+                    .get(&user_id.to_hex())
+                    .copied()
+                    .unwrap_or(RoomRole::Member)
+            };
+            let is_bot = bots.contains(&Bson::ObjectId(*user_id));
+
+            let mut membership = Membership::new(room.id, *user_id, role, is_bot);
+            membership.joined_at = joined_at;
+
+            match membership_collection.insert_one(&membership).await {
+                Ok(_) => {}
+                Err(e) if is_duplicate_key_error(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let member_count = membership_collection
+            .count_documents(doc! { "room_id": room.id })
+            .await?;
+
+        room_collection
+            .update_one(
+                doc! { "_id": room.id },
+                doc! {
+                    "$set": { "member_count": member_count as i64 },
+                    "$unset": { "participants": "", "roles": "" }
+                },
+            )
+            .await?;
+    }
+
+    if !rooms.is_empty() {
+        println!("Moved the members of {} rooms to their own collection", rooms.len());
+    }
+    Ok(())
+}
+
+pub fn is_duplicate_key_error(err: &Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000
+    )
+}
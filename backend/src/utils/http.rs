@@ -0,0 +1,190 @@
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::{
+    Url,
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect::Policy,
+};
+use sha2::Sha256;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+// Shared client for calls to integrations, per request timeouts can shorten it. Hosts are
+// resolved by PublicResolver, and neither proxies nor redirects are followed, so a
+// registered url can't lead the server to an internal address
+pub static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(Policy::none())
+        .no_proxy()
+        .build()
+        .expect("Failed to build the HTTP client")
+});
+
+// HMAC-SHA256 over "{timestamp}.{body}", so receivers can reject replays
+pub fn sign(secret: &str, timestamp: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// Integration urls must be http or https and only reach public addresses. Checked when
+// they are registered and again before every call, since the host may resolve elsewhere
+// by then. The message is meant for the client
+pub async fn check_public_url(url: &str) -> Result<(), String> {
+    let invalid = || "The url must be a valid http or https url".to_string();
+
+    let url = Url::parse(url).map_err(|_| invalid())?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(invalid());
+    }
+    let host = url.host_str().ok_or_else(invalid)?;
+
+    // Ip literals never reach the resolver of the client
+    let addrs: Vec<IpAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => tokio::net::lookup_host((host, 0))
+            .await
+            .map_err(|_| format!("{host} could not be resolved"))?
+            .map(|addr| addr.ip())
+            .collect(),
+    };
+
+    if addrs.is_empty() || !addrs.into_iter().all(is_public) {
+        return Err("The url must point to a public address".to_string());
+    }
+
+    Ok(())
+}
+
+// Resolves like the system does, but fails for hosts with a loopback, private, link local
+// or otherwise internal address
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+                return Err(format!("{host} does not resolve to a public address").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network", shared address space, protocol assignments, benchmarking, reserved
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (b == 18 || b == 19))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let [first, second, ..] = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Ipv4 compatible, unique local, link local, NAT64 and documentation
+        || first == 0
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first == 0x64 && second == 0xff9b)
+        || (first == 0x2001 && second == 0xdb8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} is internal");
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_public() {
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip} is public");
+        }
+    }
+
+    #[tokio::test]
+    async fn urls_to_internal_hosts_are_rejected() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://[::1]/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://2130706433/hook",
+            "http://localhost/hook",
+            "https://10.0.0.5/hook",
+        ] {
+            assert!(check_public_url(url).await.is_err(), "{url} is rejected");
+        }
+    }
+
+    #[tokio::test]
+    async fn urls_need_http_or_https() {
+        for url in ["ftp://1.1.1.1/hook", "file:///etc/passwd", "not a url"] {
+            assert!(check_public_url(url).await.is_err(), "{url} is rejected");
+        }
+        assert!(check_public_url("https://1.1.1.1/hook").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn the_client_does_not_resolve_internal_hosts() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = axum::Router::new().route("/", axum::routing::get(|| async { "ok" }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let url = format!("http://localhost:{port}/");
+        assert!(reqwest::Client::new().get(&url).send().await.is_ok());
+        assert!(HTTP_CLIENT.get(&url).send().await.is_err());
+    }
+}
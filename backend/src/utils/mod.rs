@@ -1 +1,4 @@
 pub mod db;
+pub mod http;
+pub mod token;
+pub mod query;
\ No newline at end of file
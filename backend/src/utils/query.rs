@@ -0,0 +1,156 @@
+use axum::http::StatusCode;
+use bson::{Bson, Document, doc, oid::ObjectId};
+
+// Makes user input match literally inside a $regex
+pub fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Longer searches only match literally, so the fuzzy pattern stays small
+const MAX_FUZZY_LEN: usize = 32;
+
+// The letters have to appear in order, so "gnrl" finds "general". Each gap only skips
+// characters other than the next letter, which keeps the match linear
+pub fn fuzzy_regex(text: &str) -> String {
+    let letters: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if letters.len() > MAX_FUZZY_LEN {
+        return escape_regex(text);
+    }
+
+    let mut pattern = String::new();
+    for (i, c) in letters.iter().enumerate() {
+        if i > 0 {
+            pattern.push_str("[^");
+            if !c.is_alphanumeric() {
+                pattern.push('\\');
+            }
+            pattern.push(*c);
+            pattern.push_str("]*");
+        }
+        pattern.push_str(&escape_regex(&c.to_string()));
+    }
+    pattern
+}
+
+#[derive(Clone, Copy)]
+pub enum SortValue {
+    Text,
+    Number,
+    Date,
+}
+
+impl SortValue {
+    fn matches(self, value: &Bson) -> bool {
+        matches!(
+            (self, value),
+            (SortValue::Text, Bson::String(_))
+                | (
+                    SortValue::Number,
+                    Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_)
+                )
+                | (SortValue::Date, Bson::DateTime(_))
+        )
+    }
+}
+
+// The cursor is the sort value and id of the last item, opaque to the client.
+// The listing name keeps a cursor from being reused with another sort
+pub fn encode_cursor(listing: &str, value: Bson, id: ObjectId) -> String {
+    let cursor = doc! { "listing": listing, "value": value, "id": id };
+    let mut bytes = Vec::new();
+    if let Err(e) = cursor.to_writer(&mut bytes) {
+        println!("Failed to encode the cursor: {e}");
+    }
+    hex::encode(bytes)
+}
+
+// The filter for the items after the cursor, sorted on `key` then `_id`
+pub fn after_cursor(
+    cursor: &str,
+    listing: &str,
+    key: &str,
+    kind: SortValue,
+    ascending: bool,
+) -> Result<Document, (StatusCode, String)> {
+    let invalid = || (StatusCode::BAD_REQUEST, "Invalid cursor".to_string());
+
+    let bytes = hex::decode(cursor).map_err(|_| invalid())?;
+    let cursor = Document::from_reader(bytes.as_slice()).map_err(|_| invalid())?;
+
+    if cursor.get_str("listing").ok() != Some(listing) {
+        return Err(invalid());
+    }
+
+    // Anything but a plain value of the sort key's type could smuggle in an operator
+    let value = cursor
+        .get("value")
+        .filter(|value| kind.matches(value))
+        .cloned()
+        .ok_or_else(invalid)?;
+    let id = cursor.get_object_id("id").map_err(|_| invalid())?;
+
+    let op = if ascending { "$gt" } else { "$lt" };
+    Ok(doc! {
+        "$or": [
+            { key: { op: value.clone() } },
+            { key: value, "_id": { op: id } }
+        ]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_gaps_skip_up_to_the_next_letter() {
+        assert_eq!(fuzzy_regex("gnrl"), "g[^n]*n[^r]*r[^l]*l");
+        assert_eq!(fuzzy_regex("a.b"), "a[^\\.]*\\.[^b]*b");
+        assert_eq!(
+            fuzzy_regex("(a+)+"),
+            "\\([^a]*a[^\\+]*\\+[^\\)]*\\)[^\\+]*\\+"
+        );
+    }
+
+    #[test]
+    fn long_searches_are_matched_literally() {
+        let search = "a".repeat(MAX_FUZZY_LEN + 1);
+        assert_eq!(fuzzy_regex(&search), search);
+    }
+
+    #[test]
+    fn cursors_keep_their_place() {
+        let id = ObjectId::new();
+        let cursor = encode_cursor("name", "general".into(), id);
+
+        let filter = after_cursor(&cursor, "name", "sort_name", SortValue::Text, true).unwrap();
+        assert_eq!(
+            filter,
+            doc! { "$or": [
+                { "sort_name": { "$gt": "general" } },
+                { "sort_name": "general", "_id": { "$gt": id } }
+            ] }
+        );
+        assert!(after_cursor(&cursor, "members", "sort_name", SortValue::Text, true).is_err());
+    }
+
+    #[test]
+    fn cursors_with_another_type_are_rejected() {
+        let id = ObjectId::new();
+        let operator = encode_cursor("name", doc! { "$ne": null }.into(), id);
+        assert!(after_cursor(&operator, "name", "sort_name", SortValue::Text, true).is_err());
+
+        let text = encode_cursor("members", "12".into(), id);
+        assert!(after_cursor(&text, "members", "member_count", SortValue::Number, false).is_err());
+
+        let number = encode_cursor("members", 12_i64.into(), id);
+        assert!(after_cursor(&number, "members", "member_count", SortValue::Number, false).is_ok());
+    }
+}
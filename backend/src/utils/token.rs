@@ -0,0 +1,19 @@
+use sha2::{Digest, Sha256};
+
+// Random secret handed out once, only its hash is stored
+pub fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    hex::encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Short code that people can type or share in a link, without look-alike characters
+pub fn generate_code(len: usize) -> String {
+    const CHARSET: &[u8] = b"abcdefghjkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    (0..len)
+        .map(|_| CHARSET[rand::random_range(0..CHARSET.len())] as char)
+        .collect()
+}